@@ -0,0 +1,226 @@
+use nalgebra::{distance, DMatrix, DVector, Point3};
+use qca_core::design::file::{QCADesign, QCADesignFile};
+use qca_core::objects::cell::{CellType, QCACellIndex};
+use qca_core::simulation::clock_generator::ClockStates;
+use qca_core::simulation::file::QCASimulationData;
+use qca_core::simulation::icha::ICHAModel;
+use qca_core::simulation::model::{SimulationModelTrait, WorkerPool};
+use qca_core::simulation::{run_simulation, SimulationOptions};
+use std::time::Instant;
+
+const DESIGN: &str = include_str!("../../examples/majority.qcd");
+const RUNS: usize = 3;
+const SOLVERS: [&str; 3] = ["schur", "symmetric", "lanczos"];
+const ASSEMBLY_ITERATIONS: usize = 10_000;
+// e^2 / (4 pi eps_0 eps_r) in meV nm for GaAs (eps_r = 12.9)
+const VQ: f64 = 1_439.964_548 / 12.9;
+
+/// Positions of the dots of every cell in the design.
+fn dot_positions(design: &QCADesign) -> Vec<Vec<Point3<f64>>> {
+    design
+        .layers
+        .iter()
+        .flat_map(|layer| {
+            let architecture = &design.cell_architectures[&layer.cell_architecture_id];
+            layer.cells.iter().map(move |cell| {
+                architecture
+                    .dot_positions
+                    .iter()
+                    .map(|[x, y]| {
+                        Point3::new(
+                            cell.position[0] + x * cell.rotation.cos() - y * cell.rotation.sin(),
+                            cell.position[1] + y * cell.rotation.cos() + x * cell.rotation.sin(),
+                            layer.z_position,
+                        )
+                    })
+                    .collect()
+            })
+        })
+        .collect()
+}
+
+/// Times the dot potentials of all cells with the couplings calculated on
+/// every iteration against the coupling matrices calculated once, and the
+/// ICHA cell updates at the null clock, which skip the eigen solver and only
+/// assemble the potentials and Hamiltonians.
+fn bench_hamiltonian_assembly(design: &QCADesign) {
+    // Dot charges relative to the neutral charge of the cell
+    let excess: Vec<DVector<f64>> = dot_positions(design)
+        .iter()
+        .enumerate()
+        .map(|(i, dots)| {
+            DVector::from_fn(dots.len(), |j, _| if (i + j) % 2 == 0 { 0.1 } else { -0.1 })
+        })
+        .collect();
+    let num_cells = excess.len();
+
+    let start = Instant::now();
+    let mut per_iteration = vec![];
+    for _ in 0..ASSEMBLY_ITERATIONS {
+        let positions = dot_positions(design);
+        per_iteration = (0..num_cells)
+            .map(|i| {
+                DVector::from_fn(positions[i].len(), |a, _| {
+                    (0..num_cells)
+                        .filter(|j| *j != i)
+                        .map(|j| {
+                            (0..positions[j].len())
+                                .map(|b| {
+                                    VQ * excess[j][b] / distance(&positions[i][a], &positions[j][b])
+                                })
+                                .sum::<f64>()
+                        })
+                        .sum()
+                })
+            })
+            .collect::<Vec<_>>();
+    }
+    let per_iteration_duration = start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    let positions = dot_positions(design);
+    let couplings: Vec<Vec<(usize, DMatrix<f64>)>> = (0..num_cells)
+        .map(|i| {
+            (0..num_cells)
+                .filter(|j| *j != i)
+                .map(|j| {
+                    let coefficients =
+                        DMatrix::from_fn(positions[i].len(), positions[j].len(), |a, b| {
+                            VQ / distance(&positions[i][a], &positions[j][b])
+                        });
+                    (j, coefficients)
+                })
+                .collect()
+        })
+        .collect();
+    let mut precomputed = vec![];
+    for _ in 0..ASSEMBLY_ITERATIONS {
+        precomputed = couplings
+            .iter()
+            .map(|cell_couplings| {
+                cell_couplings
+                    .iter()
+                    .map(|(j, coefficients)| coefficients * &excess[*j])
+                    .sum::<DVector<f64>>()
+            })
+            .collect::<Vec<_>>();
+    }
+    let precomputed_duration = start.elapsed().as_secs_f64();
+
+    let deviation = per_iteration
+        .iter()
+        .zip(precomputed.iter())
+        .map(|(a, b)| (a - b).amax())
+        .fold(0.0, f64::max);
+    println!(
+        "Dot potentials of majority.qcd ({} iterations): per-iteration couplings {:.3} s, \
+         precomputed couplings {:.3} s, max deviation {:e} meV",
+        ASSEMBLY_ITERATIONS, per_iteration_duration, precomputed_duration, deviation
+    );
+
+    let model_settings = &design.simulation_settings.simulation_model_settings["icha_model"];
+    let mut model = ICHAModel::new();
+    model
+        .deserialize_model_settings(&model_settings.model_settings.to_string())
+        .unwrap();
+    model
+        .deserialize_clock_generator_settings(&model_settings.clock_generator_settings.to_string())
+        .unwrap();
+    model.initiate(
+        Box::new(design.layers.clone()),
+        design.cell_architectures.clone(),
+    );
+    let clock_states =
+        ClockStates::zones(vec![
+            model.get_clock_generator_settings().get_amplitude_max();
+            4
+        ]);
+    let mut cell_indices = vec![];
+    let mut input_states = vec![];
+    for (i, layer) in design.layers.iter().enumerate() {
+        let dot_count = design.cell_architectures[&layer.cell_architecture_id].dot_count;
+        for (j, cell) in layer.cells.iter().enumerate() {
+            match cell.typ {
+                CellType::Normal | CellType::Output => cell_indices.push(QCACellIndex::new(i, j)),
+                CellType::Input => input_states.extend(vec![0.0; dot_count as usize / 4]),
+                CellType::Fixed => {}
+            }
+        }
+    }
+
+    let workers = WorkerPool::default();
+    let start = Instant::now();
+    for _ in 0..ASSEMBLY_ITERATIONS {
+        model.pre_calculate(&clock_states, &input_states);
+        model.calculate_all(&cell_indices, &workers);
+    }
+    println!(
+        "ICHA cell updates of majority.qcd without eigen solver ({} iterations): {:.3} s",
+        ASSEMBLY_ITERATIONS,
+        start.elapsed().as_secs_f64()
+    );
+}
+
+fn main() {
+    let design = serde_json::from_str::<QCADesignFile>(DESIGN)
+        .unwrap()
+        .design;
+    bench_hamiltonian_assembly(&design);
+
+    let model_settings = &design.simulation_settings.simulation_model_settings["icha_model"];
+
+    let mut reference: Option<QCASimulationData> = None;
+    for solver in SOLVERS {
+        let mut settings = model_settings.model_settings.clone();
+        settings["eigen_solver"] = solver.into();
+
+        let mut durations: Vec<f64> = vec![];
+        for _ in 0..RUNS {
+            let mut model = ICHAModel::new();
+            model
+                .deserialize_model_settings(&settings.to_string())
+                .unwrap();
+            model
+                .deserialize_clock_generator_settings(
+                    &model_settings.clock_generator_settings.to_string(),
+                )
+                .unwrap();
+
+            let start = Instant::now();
+            let simulation = run_simulation(
+                Box::new(model),
+                design.layers.clone(),
+                design.cell_architectures.clone(),
+                SimulationOptions::default(),
+            );
+            let duration = start.elapsed().as_secs_f64();
+            println!(
+                "majority.qcd ({}): {} samples in {:.3} s",
+                solver, simulation.metadata.num_samples, duration
+            );
+            durations.push(duration);
+
+            match &reference {
+                None => reference = Some(simulation),
+                Some(reference) => {
+                    let deviation = reference
+                        .cells_data
+                        .iter()
+                        .zip(simulation.cells_data.iter())
+                        .flat_map(|(a, b)| a.data.iter().zip(b.data.iter()))
+                        .map(|(a, b)| (a - b).abs())
+                        .fold(0.0, f64::max);
+                    println!("  max deviation from {}: {:e}", SOLVERS[0], deviation);
+                }
+            }
+        }
+
+        println!(
+            "ICHA majority.qcd ({}): best {:.3} s, mean {:.3} s over {} runs",
+            solver,
+            durations.iter().cloned().fold(f64::INFINITY, f64::min),
+            durations.iter().sum::<f64>() / RUNS as f64,
+            RUNS
+        );
+    }
+}
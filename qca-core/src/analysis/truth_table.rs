@@ -1,22 +1,75 @@
 use crate::design::file::QCADesign;
+use crate::objects::bus::Bus;
 use crate::objects::cell::QCACellIndex;
 use crate::simulation::file::QCASimulationData;
+use crate::simulation::SampleTiming;
 use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
 use std::collections::HashMap;
 use std::fmt;
 
+#[serde_inline_default]
 #[derive(Serialize, Deserialize)]
 pub struct TruthTable {
     pub entries: Vec<(String, Vec<Option<char>>)>,
+    /// Integer values of the buses decoded from their cell entries
+    #[serde_inline_default(Vec::new())]
+    pub buses: Vec<(String, Vec<Option<u64>>)>,
+}
+
+impl TruthTable {
+    /// Replaces the entries of the cells of a bus by its integer values.
+    pub fn decode_bus(&mut self, bus: &Bus) -> Result<(), String> {
+        let bits = bus
+            .bit_labels()
+            .iter()
+            .map(|label| {
+                self.entries
+                    .iter()
+                    .position(|(entry_label, _)| entry_label == label)
+                    .ok_or_else(|| format!("Bus {} has no stored cell {}", bus, label))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let len = bits
+            .iter()
+            .map(|&bit| self.entries[bit].1.len())
+            .max()
+            .unwrap_or(0);
+        let values = (0..len)
+            .map(|i| {
+                let bit_values = bits
+                    .iter()
+                    .map(|&bit| self.entries[bit].1.get(i).copied().flatten())
+                    .collect::<Vec<_>>();
+                bus.decode(&bit_values)
+            })
+            .collect();
+
+        self.entries
+            .retain(|(label, _)| !bus.bit_labels().contains(label));
+        self.buses.push((bus.name.clone(), values));
+        Ok(())
+    }
 }
 
 impl fmt::Display for TruthTable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let max_len = self.entries.iter().map(|(_, x)| x.len()).max().unwrap();
+        let max_len = self
+            .entries
+            .iter()
+            .map(|(_, x)| x.len())
+            .chain(self.buses.iter().map(|(_, x)| x.len()))
+            .max()
+            .unwrap();
         for entry in &self.entries {
             f.write_str(entry.0.as_str())?;
             f.write_str("\t")?;
         }
+        for bus in &self.buses {
+            f.write_str(bus.0.as_str())?;
+            f.write_str("\t")?;
+        }
         for i in 0..max_len {
             f.write_str("\n")?;
             for entry in &self.entries {
@@ -34,57 +87,73 @@ impl fmt::Display for TruthTable {
                 }
                 f.write_str("\t")?;
             }
+            for bus in &self.buses {
+                match bus.1.get(i).copied().flatten() {
+                    None => f.write_str("NaN")?,
+                    Some(value) => f.write_str(value.to_string().as_str())?,
+                }
+                f.write_str("\t")?;
+            }
         }
         Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Thresholds of the logical analysis, as fractions of the signal ranges.
+#[derive(Debug, Clone, Copy)]
+pub struct TruthTableThresholds {
+    /// Part of the clock range below its maximum that counts as clock high
+    pub clock: f64,
+    /// Part of the polarization range from ±1 that counts as a logical value
+    pub logical: f64,
+    /// Part of a clock region a logical value needs to hold for
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
 struct ClockRegion {
     start: usize,
     end: usize,
 }
 
-fn generate_clock_regions(
-    clock_data: &[Vec<f64>; 4],
-    clock_threshold: f64,
-) -> [Vec<ClockRegion>; 4] {
-    std::array::from_fn(|i| {
-        let clock_data = &clock_data[i];
+fn generate_clock_regions(clock_data: &[Vec<f64>], clock_threshold: f64) -> Vec<Vec<ClockRegion>> {
+    clock_data
+        .iter()
+        .map(|clock_data| {
+            let clock_high = clock_data
+                .iter()
+                .max_by(|a, b| a.partial_cmp(b).unwrap())
+                .unwrap();
+            let clock_low = clock_data
+                .iter()
+                .min_by(|a, b| a.partial_cmp(b).unwrap())
+                .unwrap();
+            let clock_high_threshold = clock_high - (clock_high - clock_low) * clock_threshold;
 
-        let clock_high = clock_data
-            .iter()
-            .max_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
-        let clock_low = clock_data
-            .iter()
-            .min_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap();
-        let clock_high_threshold = clock_high - (clock_high - clock_low) * clock_threshold;
-
-        let mut clock_regions = Vec::<ClockRegion>::new();
-        let mut current_clock_region: Option<ClockRegion> = None;
-        for (i, value) in clock_data.iter().enumerate() {
-            if *value > clock_high_threshold {
-                match current_clock_region {
-                    None => current_clock_region = Some(ClockRegion { start: i, end: i }),
-                    Some(ref mut region) => region.end = i,
+            let mut clock_regions = Vec::<ClockRegion>::new();
+            let mut current_clock_region: Option<ClockRegion> = None;
+            for (i, value) in clock_data.iter().enumerate() {
+                if *value > clock_high_threshold {
+                    match current_clock_region {
+                        None => current_clock_region = Some(ClockRegion { start: i, end: i }),
+                        Some(ref mut region) => region.end = i,
+                    }
+                } else if let Some(region) = current_clock_region {
+                    clock_regions.push(region);
+                    current_clock_region = None;
                 }
-            } else if let Some(region) = current_clock_region {
+            }
+            if let Some(region) = current_clock_region {
                 clock_regions.push(region);
-                current_clock_region = None;
             }
-        }
-        if let Some(region) = current_clock_region {
-            clock_regions.push(region);
-        }
 
-        clock_regions
-    })
+            clock_regions
+        })
+        .collect()
 }
 
-fn clean_clock_regions(clock_regions: &mut [Vec<ClockRegion>; 4]) {
-    for i in (0..4).rev() {
+fn clean_clock_regions(clock_regions: &mut [Vec<ClockRegion>]) {
+    for i in (0..clock_regions.len()).rev() {
         for j in (0..i).rev() {
             let (left, right) = clock_regions.split_at_mut(i);
             let current_clock = &mut right[0];
@@ -100,6 +169,29 @@ fn clean_clock_regions(clock_regions: &mut [Vec<ClockRegion>; 4]) {
     }
 }
 
+/// Assigns the clock regions of a clock signal lagging by `lag` samples to
+/// the input periods, keeping the last region of every period as the most
+/// settled one.
+fn group_clock_regions(
+    clock_regions: &[ClockRegion],
+    lag: f64,
+    timing: &SampleTiming,
+) -> Vec<Option<ClockRegion>> {
+    let mut periods: Vec<Option<ClockRegion>> = Vec::new();
+    for region in clock_regions {
+        let start = region.start as f64 - timing.input_offset as f64 - lag;
+        if start < 0.0 {
+            continue;
+        }
+        let period = (start / timing.samples_per_input.max(1) as f64) as usize;
+        if periods.len() <= period {
+            periods.resize(period + 1, None);
+        }
+        periods[period] = Some(*region);
+    }
+    periods
+}
+
 fn generate_logical_value(
     cell_data: &[f64],
     clock_region: &ClockRegion,
@@ -161,16 +253,19 @@ fn generate_logical_value(
         })?
 }
 
+/// Generates one logical value per clock cycle and cell, or one per input
+/// period with the `timing` of the inputs and the clock, in which case the
+/// clock delays count input periods.
 pub fn generate_truth_table(
     design: &QCADesign,
     simulation: &QCASimulationData,
     cells: &Vec<QCACellIndex>,
     cell_clock_delay: HashMap<QCACellIndex, usize>,
-    clock_threshold: f64,
-    logical_threshold: f64,
-    value_threshold: f64,
+    timing: Option<&SampleTiming>,
+    thresholds: &TruthTableThresholds,
 ) -> TruthTable {
-    let mut clock_regions = generate_clock_regions(&simulation.clock_data, clock_threshold);
+    let clock_phases = simulation.metadata.clock_phases();
+    let mut clock_regions = generate_clock_regions(&simulation.clock_data, thresholds.clock);
     clean_clock_regions(&mut clock_regions);
 
     let entries = cells
@@ -183,7 +278,7 @@ pub fn generate_truth_table(
                 .unwrap();
 
             let clock_phase_shift = design.layers[cell.layer].cells[cell.cell].clock_phase_shift;
-            let clock_index = (clock_phase_shift / 90f64).round() as usize % 4;
+            let clock_index = clock_phases.signal_index(clock_phase_shift);
 
             let clock_skip_cycles = *cell_clock_delay.get(&cell).or(Some(&0)).unwrap();
 
@@ -191,16 +286,28 @@ pub fn generate_truth_table(
                 [&design.layers[cell.layer].cell_architecture_id]
                 .dot_count
                 / 4;
-            let logical_data = clock_regions[clock_index]
+            let cell_clock_regions = match timing {
+                Some(timing) => group_clock_regions(
+                    &clock_regions[clock_index],
+                    clock_phases.offset(clock_index) * timing.samples_per_cycle as f64,
+                    timing,
+                ),
+                None => clock_regions[clock_index]
+                    .iter()
+                    .copied()
+                    .map(Some)
+                    .collect(),
+            };
+            let logical_data = cell_clock_regions
                 .iter()
                 .skip(clock_skip_cycles)
                 .map(|clock_region| {
                     generate_logical_value(
                         cell_data.data.as_slice(),
-                        clock_region,
+                        clock_region.as_ref()?,
                         polarization_count,
-                        logical_threshold,
-                        value_threshold,
+                        thresholds.logical,
+                        thresholds.value,
                     )
                 })
                 .chain((0..clock_skip_cycles).map(|_| None))
@@ -217,5 +324,201 @@ pub fn generate_truth_table(
         })
         .collect::<Vec<_>>();
 
-    TruthTable { entries }
+    TruthTable {
+        entries,
+        buses: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::design::file::SimulationSettings;
+    use crate::simulation::bistable::BistableModel;
+    use crate::simulation::file::{read_from_file, write_to_file};
+    use crate::simulation::model::SimulationModelTrait;
+    use crate::simulation::stimulus::Stimulus;
+    use crate::simulation::test_designs::{wire, wire_cells};
+    use crate::simulation::{run_simulation, SimulationOptions};
+    use std::fs::File;
+
+    fn regions(starts: &[usize], len: usize) -> Vec<ClockRegion> {
+        starts
+            .iter()
+            .map(|&start| ClockRegion {
+                start,
+                end: start + len,
+            })
+            .collect()
+    }
+
+    fn starts(periods: &[Option<ClockRegion>]) -> Vec<Option<usize>> {
+        periods
+            .iter()
+            .map(|region| region.map(|region| region.start))
+            .collect()
+    }
+
+    #[test]
+    fn test_group_clock_regions_keeps_last_cycle_of_period() {
+        // Three clock cycles of 10 samples per input period, the inputs
+        // changing 5 samples into a cycle
+        let timing = SampleTiming {
+            samples_per_input: 30,
+            samples_per_cycle: 10,
+            input_offset: 5,
+        };
+        let clock_regions = regions(&[2, 12, 22, 32, 42, 52, 62], 4);
+
+        // The region before the first input change belongs to no period
+        assert_eq!(
+            starts(&group_clock_regions(&clock_regions, 0.0, &timing)),
+            vec![Some(32), Some(62)]
+        );
+        // A lagging clock zone shifts its regions back into the periods
+        assert_eq!(
+            starts(&group_clock_regions(&clock_regions, 10.0, &timing)),
+            vec![Some(42), Some(62)]
+        );
+    }
+
+    #[test]
+    fn test_group_clock_regions_leaves_periods_without_cycle_empty() {
+        // Inputs changing faster than the clock
+        let timing = SampleTiming {
+            samples_per_input: 10,
+            samples_per_cycle: 25,
+            input_offset: 3,
+        };
+        let clock_regions = regions(&[5, 30, 55], 8);
+
+        assert_eq!(
+            starts(&group_clock_regions(&clock_regions, 0.0, &timing)),
+            vec![Some(5), None, Some(30), None, None, Some(55)]
+        );
+    }
+
+    /// Two inputs `A0` and `A1` each driving a two cell wire to the outputs
+    /// `Y0` and `Y1`. The 4-dot cells invert at every cell of a horizontal
+    /// wire, so `Y` carries `A` again.
+    #[test]
+    fn test_decode_bus_of_generated_truth_table() {
+        let (mut layers, cell_architectures) = wire(3);
+        layers[0].cells.extend(wire_cells(3, 200.0));
+        for (bit, cells) in layers[0].cells.chunks_mut(3).enumerate() {
+            cells[0].label = Some(format!("A{}", bit));
+            cells[2].label = Some(format!("Y{}", bit));
+        }
+        let design = QCADesign {
+            qca_core_version: crate::get_qca_core_version(),
+            layers,
+            cell_architectures,
+            simulation_settings: SimulationSettings::new(),
+        };
+
+        let stimulus = Stimulus::from_json(
+            r#"{"buses": {"A[0..1]": [
+                {"value": 0, "hold": 1}, {"value": 1, "hold": 1},
+                {"value": 2, "hold": 1}, {"value": 3, "hold": 1}
+            ]}}"#,
+        )
+        .unwrap();
+        let simulation = run_simulation(
+            Box::new(BistableModel::new()),
+            design.layers.clone(),
+            design.cell_architectures.clone(),
+            SimulationOptions {
+                stimulus: Some(stimulus),
+                ..SimulationOptions::default()
+            },
+        );
+
+        let mut truth_table = generate_truth_table(
+            &design,
+            &simulation,
+            &simulation.metadata.stored_cells,
+            HashMap::new(),
+            None,
+            &TruthTableThresholds {
+                clock: 0.05,
+                logical: 0.05,
+                value: 0.8,
+            },
+        );
+        for bus in ["A[0..1]", "Y[0..1]"] {
+            truth_table.decode_bus(&bus.parse().unwrap()).unwrap();
+        }
+        assert!(truth_table.entries.is_empty());
+        assert_eq!(
+            truth_table.buses,
+            vec![
+                ("A".to_string(), vec![Some(0), Some(1), Some(2), Some(3)]),
+                ("Y".to_string(), vec![Some(0), Some(1), Some(2), Some(3)]),
+            ]
+        );
+        assert!(truth_table.decode_bus(&"B[0..1]".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_three_zone_wire_round_trip() {
+        // Pairs of cells in each of the three zones, the output in the last
+        let (mut layers, cell_architectures) = wire(7);
+        for (i, cell) in layers[0].cells.iter_mut().enumerate() {
+            cell.clock_phase_shift = ((i + 1) / 2).saturating_sub(1) as f64 * 120.0;
+        }
+        let design = QCADesign {
+            qca_core_version: crate::get_qca_core_version(),
+            layers,
+            cell_architectures,
+            simulation_settings: SimulationSettings::new(),
+        };
+        let mut model = BistableModel::new();
+        model
+            .deserialize_clock_generator_settings(
+                &r#"{"num_clock_zones": 3, "extra_periods": 1}"#.to_string(),
+            )
+            .unwrap();
+        let simulation = run_simulation(
+            Box::new(model),
+            design.layers.clone(),
+            design.cell_architectures.clone(),
+            SimulationOptions::default(),
+        );
+
+        let path = std::env::temp_dir().join(format!("three_zone_wire_{}.qcs", std::process::id()));
+        write_to_file(File::create(&path).unwrap(), &design, &simulation).unwrap();
+        let (design, read) = read_from_file(File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.metadata.num_clock_zones, 3);
+        assert_eq!(read.clock_data, simulation.clock_data);
+        assert_eq!(read.clock_data.len(), 3);
+        assert_eq!(read.cells_data.len(), simulation.cells_data.len());
+        for (read, written) in read.cells_data.iter().zip(simulation.cells_data.iter()) {
+            assert_eq!(read.data, written.data);
+        }
+
+        let truth_table = generate_truth_table(
+            &design,
+            &read,
+            &read.metadata.stored_cells,
+            HashMap::new(),
+            None,
+            &TruthTableThresholds {
+                clock: 0.05,
+                logical: 0.05,
+                value: 0.8,
+            },
+        );
+        // Six inverting hops leave the output equal to the input, the extra
+        // period carries no input value
+        let values = vec![Some('A'), Some('B'), None];
+        assert_eq!(
+            truth_table.entries,
+            vec![
+                ("0-0".to_string(), values.clone()),
+                ("0-6".to_string(), values)
+            ]
+        );
+    }
 }
@@ -0,0 +1,122 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Group of cells whose labels share a prefix followed by the bit index,
+/// declared as `A[0..3]` for the cells labelled `A0` to `A3`.
+///
+/// Bit `i` of an integer value is carried by the cell labelled with index
+/// `first + i`, a set bit is polarization 1 (logic `A`) and a cleared bit
+/// polarization -1 (logic `B`).
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Bus {
+    pub name: String,
+    pub first: usize,
+    pub last: usize,
+}
+
+impl Bus {
+    /// Number of bits of the bus
+    pub fn width(&self) -> usize {
+        self.last - self.first + 1
+    }
+
+    /// Labels of the bus cells, least significant bit first
+    pub fn bit_labels(&self) -> Vec<String> {
+        (self.first..=self.last)
+            .map(|index| format!("{}{}", self.name, index))
+            .collect()
+    }
+
+    /// Whether a value fits in the bits of the bus
+    pub fn fits(&self, value: u64) -> bool {
+        self.width() >= u64::BITS as usize || value >> self.width() == 0
+    }
+
+    /// Bit of a value carried by the cell with the label, `None` if the cell
+    /// is not part of the bus.
+    pub fn bit(&self, label: &str, value: u64) -> Option<bool> {
+        let index = self
+            .bit_labels()
+            .iter()
+            .position(|bit_label| bit_label == label)?;
+        Some(index < u64::BITS as usize && (value >> index) & 1 == 1)
+    }
+
+    /// Integer value of the logic values of the bus cells, least significant
+    /// bit first, `None` if any bit is undefined.
+    pub fn decode(&self, bits: &[Option<char>]) -> Option<u64> {
+        bits.iter()
+            .enumerate()
+            .try_fold(0u64, |value, (index, bit)| match bit {
+                Some('A') => Some(value | 1u64.checked_shl(index as u32)?),
+                Some('B') => Some(value),
+                _ => None,
+            })
+    }
+}
+
+impl FromStr for Bus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Bus '{}' must be formatted as <Name>[<First>..<Last>]", s);
+        let (name, range) = s
+            .strip_suffix(']')
+            .and_then(|s| s.split_once('['))
+            .ok_or_else(invalid)?;
+        let (first, last) = range.split_once("..").ok_or_else(invalid)?;
+        let first = first.trim().parse::<usize>().map_err(|_| invalid())?;
+        let last = last.trim().parse::<usize>().map_err(|_| invalid())?;
+        if name.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Bus {
+            name: name.to_string(),
+            first: first.min(last),
+            last: first.max(last),
+        })
+    }
+}
+
+impl Display for Bus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}[{}..{}]", self.name, self.first, self.last)
+    }
+}
+
+impl Serialize for Bus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Bus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bus_bits() {
+        let bus: Bus = "A[0..3]".parse().unwrap();
+        assert_eq!(bus.bit_labels(), vec!["A0", "A1", "A2", "A3"]);
+        assert_eq!(bus.bit("A2", 0b0101), Some(true));
+        assert_eq!(bus.bit("A1", 0b0101), Some(false));
+        assert_eq!(bus.bit("B1", 0b0101), None);
+        assert!(bus.fits(0b1111));
+        assert!(!bus.fits(0b10000));
+        assert_eq!(
+            bus.decode(&[Some('A'), Some('B'), Some('A'), Some('B')]),
+            Some(0b0101)
+        );
+        assert_eq!(bus.decode(&[Some('A'), None, Some('A'), Some('B')]), None);
+        assert!("A[0-3]".parse::<Bus>().is_err());
+    }
+}
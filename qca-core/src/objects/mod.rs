@@ -1,4 +1,5 @@
 pub mod architecture;
+pub mod bus;
 pub mod cell;
 pub mod generator;
 pub mod layer;
@@ -0,0 +1,123 @@
+use crate::objects::cell::QCACellIndex;
+use crate::objects::layer::QCALayer;
+
+/// Dense ids for the cells of a design, numbering the cells layer by layer.
+pub struct CellIds {
+    layer_offsets: Vec<usize>,
+}
+
+impl CellIds {
+    pub fn new(layers: &[QCALayer]) -> Self {
+        let mut layer_offsets = Vec::with_capacity(layers.len() + 1);
+        layer_offsets.push(0);
+        for layer in layers {
+            layer_offsets.push(layer_offsets.last().unwrap() + layer.cells.len());
+        }
+        CellIds { layer_offsets }
+    }
+
+    pub fn id(&self, cell_index: &QCACellIndex) -> usize {
+        self.layer_offsets[cell_index.layer] + cell_index.cell
+    }
+
+    pub fn len(&self) -> usize {
+        *self.layer_offsets.last().unwrap()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Per-cell state vectors of possibly different lengths, stored back to back
+/// in one buffer for the current iteration and one for the previous one.
+pub struct CellStates {
+    offsets: Vec<usize>,
+    previous: Vec<f64>,
+    current: Vec<f64>,
+}
+
+impl CellStates {
+    pub fn new(states: &[Vec<f64>]) -> Self {
+        let mut offsets = Vec::with_capacity(states.len() + 1);
+        offsets.push(0);
+        for state in states {
+            offsets.push(offsets.last().unwrap() + state.len());
+        }
+        let current: Vec<f64> = states.concat();
+        CellStates {
+            offsets,
+            previous: current.clone(),
+            current,
+        }
+    }
+
+    /// State of the cell at the end of the previous iteration.
+    pub fn previous(&self, id: usize) -> &[f64] {
+        &self.previous[self.offsets[id]..self.offsets[id + 1]]
+    }
+
+    /// Latest state of the cell.
+    pub fn current(&self, id: usize) -> &[f64] {
+        &self.current[self.offsets[id]..self.offsets[id + 1]]
+    }
+
+    pub fn set_current(&mut self, id: usize, state: &[f64]) {
+        self.current[self.offsets[id]..self.offsets[id + 1]].copy_from_slice(state);
+    }
+
+    /// Moves the current state of a cell `relaxation` of the way towards
+    /// `state` and returns the largest difference between the old and the
+    /// undamped new state.
+    pub fn relax(&mut self, id: usize, state: &[f64], relaxation: f64) -> f64 {
+        let mut residual: f64 = 0.0;
+        for (value, new) in self.current[self.offsets[id]..self.offsets[id + 1]]
+            .iter_mut()
+            .zip(state)
+        {
+            residual = residual.max((new - *value).abs());
+            *value = (1.0 - relaxation) * *value + relaxation * new;
+        }
+        residual
+    }
+
+    /// Sets the state of a cell that is not calculated, e.g. an input.
+    pub fn set_fixed(&mut self, id: usize, state: &[f64]) {
+        self.previous[self.offsets[id]..self.offsets[id + 1]].copy_from_slice(state);
+        self.set_current(id, state);
+    }
+
+    /// Starts a new iteration, making the current states the previous ones.
+    pub fn advance(&mut self) {
+        self.previous.copy_from_slice(&self.current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_advance_keeps_previous_iteration() {
+        let mut states = CellStates::new(&[vec![0.5; 4], vec![0.25; 8]]);
+        states.set_current(1, &[1.0; 8]);
+        assert_eq!(states.previous(1), &[0.25; 8]);
+        assert_eq!(states.current(0), &[0.5; 4]);
+
+        states.advance();
+        states.set_current(1, &[0.0; 8]);
+        assert_eq!(states.previous(1), &[1.0; 8]);
+
+        states.set_fixed(0, &[1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(states.previous(0), states.current(0));
+    }
+
+    #[test]
+    fn test_relax_reports_undamped_residual() {
+        let mut states = CellStates::new(&[vec![0.0, 1.0]]);
+        assert_eq!(states.relax(0, &[1.0, 1.0], 0.25), 1.0);
+        assert_eq!(states.current(0), &[0.25, 1.0]);
+        assert_eq!(states.relax(0, &[0.5, 0.75], 1.0), 0.25);
+        assert_eq!(states.current(0), &[0.5, 0.75]);
+    }
+}
@@ -1,33 +1,487 @@
 use crate::objects::generator::{Generator, GeneratorConfig};
-use std::convert::TryInto;
+use crate::objects::layer::QCALayer;
+use crate::simulation::settings::{InputDescriptor, OptionsEntry, OptionsList, SelectOption};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::f64::consts::PI;
 
+/// Shape of the clock signal over one period, which runs through the switch,
+/// hold, release and relax phases.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockShape {
+    /// Raised-cosine switch and release ramps between flat hold and relax
+    /// phases.
+    RaisedCosine,
+    /// Linear switch and release ramps between flat hold and relax phases.
+    Trapezoidal,
+    /// Cosine scaled by the amplitude factor and clipped at the amplitude
+    /// limits. A factor of 0.5 gives a pure sine, QCADesigner uses 2.
+    Sinusoidal,
+}
+
+/// Waveform of the clock signal, shared by all clock zones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockWaveform {
+    pub shape: ClockShape,
+    /// Fraction of the period taken by each of the switch and release ramps
+    pub ramp_fraction: f64,
+    /// Fraction of the period the cells are held polarized
+    pub hold_fraction: f64,
+    /// Amplitude of the sinusoidal clock relative to the amplitude range
+    pub amplitude_factor: f64,
+}
+
+impl Default for ClockWaveform {
+    fn default() -> Self {
+        ClockWaveform {
+            shape: ClockShape::RaisedCosine,
+            ramp_fraction: 0.25,
+            hold_fraction: 0.25,
+            amplitude_factor: 0.5,
+        }
+    }
+}
+
+impl ClockWaveform {
+    /// Activation of the clock at `phase` (fraction of the period), from 0
+    /// while the cells relax at the maximum amplitude to 1 while they are
+    /// held at the minimum amplitude.
+    pub fn activation(&self, phase: f64) -> f64 {
+        let ramp = self.ramp_fraction.clamp(0.0, 0.5);
+        let hold = self.hold_fraction.clamp(0.0, 1.0 - 2.0 * ramp);
+        let release_start = ramp + hold;
+
+        match self.shape {
+            ClockShape::RaisedCosine => {
+                if phase < ramp {
+                    (1.0 + ((1.0 - phase / ramp) * PI).cos()) / 2.0
+                } else if phase < release_start {
+                    1.0
+                } else if phase < release_start + ramp {
+                    (1.0 + (PI * (phase - release_start) / ramp).cos()) / 2.0
+                } else {
+                    0.0
+                }
+            }
+            ClockShape::Trapezoidal => {
+                if phase < ramp {
+                    phase / ramp
+                } else if phase < release_start {
+                    1.0
+                } else if phase < release_start + ramp {
+                    1.0 - (phase - release_start) / ramp
+                } else {
+                    0.0
+                }
+            }
+            // Peaks in the middle of the hold phase of the default waveform
+            ClockShape::Sinusoidal => {
+                (0.5 + self.amplitude_factor * (2.0 * PI * (phase - 0.375)).cos()).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// Externally designed clock signal used instead of the generated waveform,
+/// e.g. a clock measured on a fabricated device. Values follow the
+/// convention of the stored clock data, the negated clock energy in meV.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ClockSignal {
+    /// Breakpoints of (phase as fraction of the period, clock value),
+    /// linearly interpolated and repeated every period. A single list is
+    /// shared by all zones, each lagging the previous by an equal share of
+    /// the period, otherwise there is one list per zone.
+    PiecewiseLinear { breakpoints: Vec<Vec<(f64, f64)>> },
+    /// Clock values of every sample, one list per zone, repeated from the
+    /// start if the run has more samples.
+    Samples { values: Vec<Vec<f64>> },
+}
+
+impl ClockSignal {
+    /// Parses a piecewise-linear clock from CSV rows of a phase followed by
+    /// the clock value of one shared or of every zone.
+    pub fn from_piecewise_linear_csv(contents: &str) -> Result<Self, String> {
+        let rows = parse_csv_rows(contents)?;
+        if rows[0].len() < 2 {
+            return Err("Piecewise-linear clock rows need a phase and a value".into());
+        }
+        let mut breakpoints: Vec<Vec<(f64, f64)>> = (1..rows[0].len())
+            .map(|zone| rows.iter().map(|row| (row[0], row[zone])).collect())
+            .collect();
+        for points in breakpoints.iter_mut() {
+            if points.iter().any(|(phase, _)| !(0.0..=1.0).contains(phase)) {
+                return Err("Piecewise-linear clock phases must be within 0 and 1".into());
+            }
+            points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        }
+        Ok(ClockSignal::PiecewiseLinear { breakpoints })
+    }
+
+    /// Parses per-sample clock values from CSV rows with one column per
+    /// zone.
+    pub fn from_samples_csv(contents: &str) -> Result<Self, String> {
+        let rows = parse_csv_rows(contents)?;
+        let values = (0..rows[0].len())
+            .map(|zone| rows.iter().map(|row| row[zone]).collect())
+            .collect();
+        Ok(ClockSignal::Samples { values })
+    }
+
+    /// Number of clock zones the signal defines, `None` if it is shared by
+    /// any number of zones.
+    pub fn num_zones(&self) -> Option<usize> {
+        match self {
+            ClockSignal::PiecewiseLinear { breakpoints } if breakpoints.len() == 1 => None,
+            ClockSignal::PiecewiseLinear { breakpoints } => Some(breakpoints.len()),
+            ClockSignal::Samples { values } => Some(values.len()),
+        }
+    }
+}
+
+/// Parses numeric CSV rows, skipping empty lines, `#` comments and a header
+/// row, and checks that all rows have the same number of columns.
+fn parse_csv_rows(contents: &str) -> Result<Vec<Vec<f64>>, String> {
+    let mut rows: Vec<Vec<f64>> = Vec::new();
+    for (line_index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let row = line
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>();
+        match row {
+            Ok(row) => {
+                if !rows.is_empty() && rows[0].len() != row.len() {
+                    return Err(format!(
+                        "Line {} has {} columns, expected {}",
+                        line_index + 1,
+                        row.len(),
+                        rows[0].len()
+                    ));
+                }
+                rows.push(row);
+            }
+            Err(_) if rows.is_empty() => continue,
+            Err(error) => return Err(format!("Line {}: {}", line_index + 1, error)),
+        }
+    }
+    if rows.is_empty() {
+        return Err("Clock signal contains no values".into());
+    }
+    Ok(rows)
+}
+
+/// Linearly interpolates periodic breakpoints sorted by phase.
+fn interpolate_periodic(points: &[(f64, f64)], phase: f64) -> f64 {
+    let next = points.partition_point(|(point_phase, _)| *point_phase <= phase);
+    let (start, end) = match next {
+        0 => {
+            let (last_phase, last_value) = points[points.len() - 1];
+            ((last_phase - 1.0, last_value), points[0])
+        }
+        n if n == points.len() => {
+            let (first_phase, first_value) = points[0];
+            (points[n - 1], (first_phase + 1.0, first_value))
+        }
+        n => (points[n - 1], points[n]),
+    };
+    if end.0 <= start.0 {
+        return start.1;
+    }
+    start.1 + (end.1 - start.1) * (phase - start.0) / (end.0 - start.0)
+}
+
+/// Random perturbations of the clock signals, drawn from normal
+/// distributions with the given standard deviations.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct ClockNoise {
+    /// Seed of the random number generator
+    pub seed: u64,
+    /// Amplitude noise of every sample and signal, relative to the
+    /// amplitude range
+    pub amplitude: f64,
+    /// Phase jitter of every sample and signal, as fraction of the period
+    pub jitter: f64,
+    /// Constant phase skew of every signal, as fraction of the period
+    pub skew: f64,
+}
+
+impl ClockNoise {
+    fn rng(&self, stream: u64) -> SmallRng {
+        SmallRng::seed_from_u64(self.seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+}
+
+/// Draws from the standard normal distribution (Box-Muller transform)
+fn standard_normal(rng: &mut SmallRng) -> f64 {
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+}
+
+/// Options entries for the clock timing, zone and waveform settings, shared
+/// by the models.
+pub fn clock_waveform_options() -> OptionsList {
+    vec![
+        OptionsEntry::Input {
+            unique_id: "samples_per_cycle".into(),
+            name: "Samples per cycle".into(),
+            description: "Samples per clock cycle independent of the input period, 0 runs the number of cycles per input".into(),
+            descriptor: InputDescriptor::NumberInput {
+                min: Some(0.0),
+                max: None,
+                unit: None,
+                whole_num: true,
+            },
+        },
+        OptionsEntry::Input {
+            unique_id: "input_offset".into(),
+            name: "Input offset".into(),
+            description: "Samples by which input changes lag the start of a clock cycle".into(),
+            descriptor: InputDescriptor::NumberInput {
+                min: Some(0.0),
+                max: None,
+                unit: None,
+                whole_num: true,
+            },
+        },
+        OptionsEntry::Input {
+            unique_id: "num_clock_zones".into(),
+            name: "Clock zones".into(),
+            description:
+                "Number of clock zones, each lagging the previous by an equal share of the period"
+                    .into(),
+            descriptor: InputDescriptor::NumberInput {
+                min: Some(1.0),
+                max: None,
+                unit: None,
+                whole_num: true,
+            },
+        },
+        OptionsEntry::Input {
+            unique_id: "continuous_clock_phase".into(),
+            name: "Continuous clock phase".into(),
+            description:
+                "Clock every cell at its exact phase shift instead of the clock zone containing it".into(),
+            descriptor: InputDescriptor::BoolInput {},
+        },
+        OptionsEntry::Input {
+            unique_id: "clock_shape".into(),
+            name: "Clock shape".into(),
+            description: "Shape of the clock signal over one period".into(),
+            descriptor: InputDescriptor::SelectInput {
+                options: vec![
+                    SelectOption {
+                        value: "raised_cosine".into(),
+                        label: "Raised cosine".into(),
+                    },
+                    SelectOption {
+                        value: "trapezoidal".into(),
+                        label: "Trapezoidal".into(),
+                    },
+                    SelectOption {
+                        value: "sinusoidal".into(),
+                        label: "Sinusoidal".into(),
+                    },
+                ],
+            },
+        },
+        OptionsEntry::Input {
+            unique_id: "ramp_fraction".into(),
+            name: "Ramp fraction".into(),
+            description: "Fraction of the period taken by each of the switch and release ramps"
+                .into(),
+            descriptor: InputDescriptor::NumberInput {
+                min: Some(0.0),
+                max: Some(0.5),
+                unit: None,
+                whole_num: false,
+            },
+        },
+        OptionsEntry::Input {
+            unique_id: "hold_fraction".into(),
+            name: "Hold fraction".into(),
+            description: "Fraction of the period the cells are held polarized".into(),
+            descriptor: InputDescriptor::NumberInput {
+                min: Some(0.0),
+                max: Some(1.0),
+                unit: None,
+                whole_num: false,
+            },
+        },
+        OptionsEntry::Input {
+            unique_id: "amplitude_factor".into(),
+            name: "Amplitude factor".into(),
+            description: "Amplitude of the sinusoidal clock relative to the amplitude range".into(),
+            descriptor: InputDescriptor::NumberInput {
+                min: Some(0.0),
+                max: None,
+                unit: None,
+                whole_num: false,
+            },
+        },
+    ]
+}
+
 /// Configuration for clock generator
 #[derive(Clone)]
 pub struct ClockConfig {
     /// Number of samples
     pub num_samples: usize,
-    /// Number of clock cycles
-    pub num_cycles: usize,
+    /// Number of samples per clock cycle
+    pub samples_per_cycle: usize,
     /// Minimum amplitude value
     pub amplitude_min: f64,
     /// Maximum amplitude value
     pub amplitude_max: f64,
+    /// Clock signals to generate
+    pub phases: ClockPhases,
+    /// Waveform of the clock signal
+    pub waveform: ClockWaveform,
+    /// External clock signal replacing the waveform
+    pub signal: Option<ClockSignal>,
+    /// Random perturbations of the clock signals
+    pub noise: Option<ClockNoise>,
 }
 
 impl GeneratorConfig for ClockConfig {}
 
-/// Generator for clock values that produces arrays of 4 clock values
+/// Default number of clock zones, the usual four-phase clocking scheme
+pub const DEFAULT_CLOCK_ZONE_COUNT: usize = 4;
+
+/// Resolve the clock zone of a cell from its clock phase shift in degrees,
+/// where the zones are spread evenly over the full period and a zone covers
+/// the phase shifts from its start up to the start of the next zone
+pub fn get_clock_zone(clock_phase_shift: f64, num_zones: usize) -> usize {
+    let num_zones = num_zones.max(1);
+    let zone_width = 360.0 / num_zones as f64;
+    (clock_phase_shift.rem_euclid(360.0) / zone_width).floor() as usize % num_zones
+}
+
+/// Assignment of the cells to the clock signals of a run.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum ClockPhases {
+    /// Evenly spaced clock zones, every cell is driven by the zone whose
+    /// range contains its clock phase shift.
+    Zones { count: usize },
+    /// One clock signal per distinct clock phase shift of the cells (degrees
+    /// in `[0, 360)`, sorted), evaluated at exactly that phase offset.
+    Continuous { phase_shifts: Vec<f64> },
+}
+
+impl ClockPhases {
+    /// Clock signals at the exact phase shifts of all cells of the layers
+    pub fn continuous(layers: &[QCALayer]) -> Self {
+        let mut phase_shifts: Vec<f64> = layers
+            .iter()
+            .flat_map(|layer| layer.cells.iter())
+            .map(|cell| cell.clock_phase_shift.rem_euclid(360.0))
+            .collect();
+        phase_shifts.sort_by(f64::total_cmp);
+        phase_shifts.dedup();
+        if phase_shifts.is_empty() {
+            phase_shifts.push(0.0);
+        }
+        ClockPhases::Continuous { phase_shifts }
+    }
+
+    /// Number of clock signals
+    pub fn num_signals(&self) -> usize {
+        match self {
+            ClockPhases::Zones { count } => (*count).max(1),
+            ClockPhases::Continuous { phase_shifts } => phase_shifts.len(),
+        }
+    }
+
+    /// Lag of a clock signal behind the unshifted clock as a fraction of the
+    /// period
+    pub fn offset(&self, signal: usize) -> f64 {
+        match self {
+            ClockPhases::Zones { .. } => signal as f64 / self.num_signals() as f64,
+            ClockPhases::Continuous { phase_shifts } => phase_shifts[signal] / 360.0,
+        }
+    }
+
+    /// Index of the clock signal driving a cell with the given clock phase
+    /// shift in degrees
+    pub fn signal_index(&self, clock_phase_shift: f64) -> usize {
+        match self {
+            ClockPhases::Zones { count } => get_clock_zone(clock_phase_shift, *count),
+            ClockPhases::Continuous { phase_shifts } => {
+                let phase = clock_phase_shift.rem_euclid(360.0);
+                let next = phase_shifts
+                    .partition_point(|shift| *shift < phase)
+                    .min(phase_shifts.len() - 1);
+                if next > 0 && phase - phase_shifts[next - 1] < (phase_shifts[next] - phase).abs() {
+                    next - 1
+                } else {
+                    next
+                }
+            }
+        }
+    }
+}
+
+/// Clock values of a sample, one per clock signal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClockStates {
+    pub phases: ClockPhases,
+    pub values: Vec<f64>,
+}
+
+impl ClockStates {
+    /// Clock values of evenly spaced clock zones
+    pub fn zones(values: Vec<f64>) -> Self {
+        ClockStates {
+            phases: ClockPhases::Zones {
+                count: values.len(),
+            },
+            values,
+        }
+    }
+
+    /// Clock value driving a cell with the given clock phase shift in degrees
+    pub fn value(&self, clock_phase_shift: f64) -> f64 {
+        self.values[self.phases.signal_index(clock_phase_shift)]
+    }
+}
+
+impl Default for ClockStates {
+    fn default() -> Self {
+        ClockStates::zones(vec![0.0; DEFAULT_CLOCK_ZONE_COUNT])
+    }
+}
+
+/// Generator for clock values that produces one clock value per clock signal
 pub struct ClockGenerator {
     config: ClockConfig,
+    /// Phase skew of every clock signal
+    skews: Vec<f64>,
 }
 
 impl Generator for ClockGenerator {
     type Config = ClockConfig;
-    type Output = [f64; 4];
+    type Output = Vec<f64>;
 
     fn new(config: Self::Config) -> Self {
-        Self { config }
+        let num_signals = config.phases.num_signals();
+        let skews = match config.noise {
+            // Stream 0 is the skew, samples use the streams from 1
+            Some(noise) if noise.skew != 0.0 => {
+                let mut rng = noise.rng(0);
+                (0..num_signals)
+                    .map(|_| noise.skew * standard_normal(&mut rng))
+                    .collect()
+            }
+            _ => vec![0.0; num_signals],
+        };
+        Self { config, skews }
     }
 
     fn generate(&self, sample: usize) -> Option<Self::Output> {
@@ -35,8 +489,7 @@ impl Generator for ClockGenerator {
             return None;
         }
 
-        let clock_cycles = self.config.num_cycles;
-        let samples_per_cycle = self.config.num_samples / clock_cycles;
+        let samples_per_cycle = self.config.samples_per_cycle.max(1);
         let ampl_min = self.config.amplitude_min;
         let ampl_max = self.config.amplitude_max;
 
@@ -46,26 +499,62 @@ impl Generator for ClockGenerator {
         //     0.0
         // };
 
-        let sample_fac = sample.rem_euclid(samples_per_cycle) as f64 / samples_per_cycle as f64;
-        Some(
-            (0..4)
+        let phases = &self.config.phases;
+        let num_signals = phases.num_signals();
+
+        let (jitters, amplitude_noise) = match self.config.noise {
+            Some(noise) => {
+                let mut rng = noise.rng(sample as u64 + 1);
+                (0..num_signals)
+                    .map(|_| {
+                        (
+                            noise.jitter * standard_normal(&mut rng),
+                            noise.amplitude * (ampl_max - ampl_min) * standard_normal(&mut rng),
+                        )
+                    })
+                    .unzip()
+            }
+            None => (vec![0.0; num_signals], vec![0.0; num_signals]),
+        };
+        let phase_of = |i: usize, shift: f64| -> f64 {
+            let sample_fac = sample.rem_euclid(samples_per_cycle) as f64 / samples_per_cycle as f64;
+            (sample_fac - shift + self.skews[i] + jitters[i]).rem_euclid(1.0)
+        };
+
+        let clock_values: Vec<f64> = match &self.config.signal {
+            Some(ClockSignal::Samples { values }) => values
+                .iter()
+                .map(|zone| zone[sample % zone.len()])
+                .collect(),
+            Some(ClockSignal::PiecewiseLinear { breakpoints }) => (0..num_signals)
                 .map(|i| {
-                    let mut clock = sample_fac - (i as f64 * 0.25);
-                    clock = clock.rem_euclid(1.0);
-                    if clock < 0.25 {
-                        (1.0 + ((1.0 - clock / 0.25) * PI).cos()) / 2.0
-                    } else if clock < 0.5 {
-                        1.0
-                    } else if clock < 0.75 {
-                        (1.0 + (PI * (clock - 0.5) / 0.25).cos()) / 2.0
+                    if breakpoints.len() == 1 {
+                        interpolate_periodic(&breakpoints[0], phase_of(i, phases.offset(i)))
                     } else {
-                        0.0
+                        interpolate_periodic(&breakpoints[i % breakpoints.len()], phase_of(i, 0.0))
                     }
                 })
+                .collect(),
+            None => (0..num_signals)
+                .map(|i| {
+                    self.config
+                        .waveform
+                        .activation(phase_of(i, phases.offset(i)))
+                })
                 .map(|v| -((ampl_max - ampl_min) * (1.0 - v) + ampl_min))
-                .collect::<Vec<f64>>()
-                .try_into()
-                .unwrap(),
+                .collect(),
+        };
+
+        if self.config.noise.is_none() {
+            return Some(clock_values);
+        }
+        // The noise must not switch the sign of the clock
+        Some(
+            clock_values
+                .into_iter()
+                .zip(amplitude_noise)
+                .map(|(value, noise)| (value + noise).min(-ampl_min.abs()))
+                .collect(),
         )
     }
 
@@ -73,3 +562,177 @@ impl Generator for ClockGenerator {
         self.config.num_samples
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_clock_zone() {
+        assert_eq!(get_clock_zone(0.0, 4), 0);
+        assert_eq!(get_clock_zone(90.0, 4), 1);
+        assert_eq!(get_clock_zone(180.0, 4), 2);
+        assert_eq!(get_clock_zone(270.0, 4), 3);
+        assert_eq!(get_clock_zone(360.0, 4), 0);
+        assert_eq!(get_clock_zone(-90.0, 4), 3);
+        assert_eq!(get_clock_zone(89.999, 4), 0);
+        assert_eq!(get_clock_zone(135.0, 4), 1);
+        assert_eq!(get_clock_zone(-45.0, 4), 3);
+        assert_eq!(get_clock_zone(120.0, 3), 1);
+        assert_eq!(get_clock_zone(240.0, 3), 2);
+        assert_eq!(get_clock_zone(300.0, 3), 2);
+    }
+
+    #[test]
+    fn test_continuous_clock_phases() {
+        let phases = ClockPhases::Continuous {
+            phase_shifts: vec![0.0, 45.0, 90.0],
+        };
+        assert_eq!(phases.signal_index(45.0), 1);
+        assert_eq!(phases.signal_index(-270.0), 2);
+        assert_eq!(phases.offset(1), 0.125);
+
+        let generator = ClockGenerator::new(ClockConfig {
+            num_samples: 8,
+            samples_per_cycle: 8,
+            amplitude_min: 1e-3,
+            amplitude_max: 65.0,
+            phases,
+            waveform: ClockWaveform::default(),
+            signal: None,
+            noise: None,
+        });
+        let zones = ClockGenerator::new(ClockConfig {
+            phases: ClockPhases::Zones { count: 8 },
+            ..generator.config.clone()
+        });
+        for sample in 0..8 {
+            let continuous = generator.generate(sample).unwrap();
+            let zones = zones.generate(sample).unwrap();
+            assert_eq!(continuous, vec![zones[0], zones[1], zones[2]]);
+        }
+    }
+
+    #[test]
+    fn test_clock_noise() {
+        let config = ClockConfig {
+            num_samples: 40,
+            samples_per_cycle: 20,
+            amplitude_min: 1e-3,
+            amplitude_max: 65.0,
+            phases: ClockPhases::Zones { count: 4 },
+            waveform: ClockWaveform::default(),
+            signal: None,
+            noise: None,
+        };
+        let noise = ClockNoise {
+            seed: 7,
+            amplitude: 0.05,
+            jitter: 0.01,
+            skew: 0.02,
+        };
+        let clean: Vec<Vec<f64>> = ClockGenerator::new(config.clone()).iter().collect();
+        let quiet: Vec<Vec<f64>> = ClockGenerator::new(ClockConfig {
+            noise: Some(ClockNoise {
+                amplitude: 0.0,
+                jitter: 0.0,
+                skew: 0.0,
+                ..noise
+            }),
+            ..config.clone()
+        })
+        .iter()
+        .collect();
+        assert_eq!(clean, quiet);
+
+        let noisy = ClockGenerator::new(ClockConfig {
+            noise: Some(noise),
+            ..config.clone()
+        });
+        assert_eq!(
+            noisy.iter().collect::<Vec<_>>(),
+            noisy.iter().collect::<Vec<_>>()
+        );
+        assert_ne!(noisy.iter().collect::<Vec<_>>(), clean);
+        assert!(noisy.iter().flatten().all(|value| value <= -1e-3));
+    }
+
+    #[test]
+    fn test_clock_signal() {
+        let signal = ClockSignal::from_piecewise_linear_csv(
+            "phase,value\n# switch\n0.0,-65\n0.25,-0.001\n0.5,-0.001\n0.75,-65\n",
+        )
+        .unwrap();
+        assert_eq!(signal.num_zones(), None);
+        let generator = ClockGenerator::new(ClockConfig {
+            num_samples: 8,
+            samples_per_cycle: 8,
+            amplitude_min: 1e-3,
+            amplitude_max: 65.0,
+            phases: ClockPhases::Zones { count: 4 },
+            waveform: ClockWaveform::default(),
+            signal: Some(signal),
+            noise: None,
+        });
+        let first = generator.generate(1).unwrap();
+        assert!((first[0] - (-65.0 + 64.999 / 2.0)).abs() < 1e-12);
+        assert_eq!(first[1], -65.0);
+        assert_eq!(first[3], -0.001);
+
+        let signal = ClockSignal::from_samples_csv("-1,-2\n-3,-4\n").unwrap();
+        assert_eq!(signal.num_zones(), Some(2));
+        let generator = ClockGenerator::new(ClockConfig {
+            num_samples: 3,
+            samples_per_cycle: 3,
+            amplitude_min: 1e-3,
+            amplitude_max: 65.0,
+            phases: ClockPhases::Zones { count: 2 },
+            waveform: ClockWaveform::default(),
+            signal: Some(signal),
+            noise: None,
+        });
+        assert_eq!(
+            generator.iter().collect::<Vec<_>>(),
+            vec![vec![-1.0, -2.0], vec![-3.0, -4.0], vec![-1.0, -2.0]]
+        );
+        assert!(ClockSignal::from_samples_csv("-1,-2\n-3\n").is_err());
+    }
+
+    #[test]
+    fn test_clock_waveform_shapes() {
+        let raised_cosine = ClockWaveform::default();
+        for (phase, expected) in [
+            (0.0, 0.0),
+            (0.125, 0.5),
+            (0.3, 1.0),
+            (0.625, 0.5),
+            (0.9, 0.0),
+        ] {
+            assert!((raised_cosine.activation(phase) - expected).abs() < 1e-12);
+        }
+
+        let trapezoidal = ClockWaveform {
+            shape: ClockShape::Trapezoidal,
+            ramp_fraction: 0.1,
+            hold_fraction: 0.5,
+            ..ClockWaveform::default()
+        };
+        for (phase, expected) in [(0.05, 0.5), (0.3, 1.0), (0.65, 0.5), (0.8, 0.0)] {
+            assert!((trapezoidal.activation(phase) - expected).abs() < 1e-12);
+        }
+
+        let sinusoidal = ClockWaveform {
+            shape: ClockShape::Sinusoidal,
+            ..ClockWaveform::default()
+        };
+        assert!((sinusoidal.activation(0.375) - 1.0).abs() < 1e-12);
+        assert!((sinusoidal.activation(0.625) - 0.5).abs() < 1e-12);
+        assert!(sinusoidal.activation(0.875).abs() < 1e-12);
+        let clipped = ClockWaveform {
+            amplitude_factor: 2.0,
+            ..sinusoidal
+        };
+        assert_eq!(clipped.activation(0.5), 1.0);
+        assert_eq!(clipped.activation(0.75), 0.0);
+    }
+}
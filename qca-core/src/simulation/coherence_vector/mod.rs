@@ -0,0 +1,598 @@
+use super::{CellType, QCACellArchitecture, SimulationModelTrait};
+use crate::objects::cell::{
+    dot_probability_distribution_to_polarization, polarization_to_dot_probability_distribution,
+    QCACell, QCACellIndex,
+};
+use crate::objects::layer::QCALayer;
+use crate::simulation::bistable::{kink_energy_neighborhood, BistableNeighbor};
+use crate::simulation::cell_state::{CellIds, CellStates};
+use crate::simulation::clock_generator::ClockStates;
+use crate::simulation::model::{
+    clock_generator_options, ClockGeneratorSettings, ClockGeneratorSettingsTrait,
+    SimulationModelSettingsTrait, WorkerPool, K_B,
+};
+use crate::simulation::settings::{InputDescriptor, OptionsEntry, OptionsList};
+use nalgebra::{DMatrix, DVector, SymmetricEigen};
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use std::collections::HashMap;
+
+const HBAR: f64 = 6.582_119_569e-13; // Reduced Planck constant [meV·s]
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CoherenceVectorModelSettings {
+    #[serde_inline_default(1e-16)]
+    time_step: f64,
+
+    #[serde_inline_default(1e-13)]
+    sample_time: f64,
+
+    #[serde_inline_default(1e-15)]
+    relaxation_time: f64,
+
+    #[serde_inline_default(1.0)]
+    temperature: f64,
+
+    #[serde_inline_default(65.0)]
+    neighborhood_radius: f64,
+
+    #[serde_inline_default(12.9)]
+    relative_permitivity: f64,
+}
+
+impl CoherenceVectorModelSettings {
+    pub fn new() -> Self {
+        serde_json::from_str::<CoherenceVectorModelSettings>("{}").unwrap()
+    }
+}
+
+impl Default for CoherenceVectorModelSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Every iteration integrates the full sample time.
+impl SimulationModelSettingsTrait for CoherenceVectorModelSettings {
+    fn relaxes_samples(&self) -> bool {
+        false
+    }
+}
+
+/// Density matrix of a cell in the basis of the positive and negative
+/// polarization state of every axis, split into its real (symmetric) and
+/// imaginary (antisymmetric) part. For two-state cells it carries the same
+/// information as the coherence vector.
+#[derive(Debug, Clone)]
+struct DensityMatrix {
+    real: DMatrix<f64>,
+    imag: DMatrix<f64>,
+}
+
+impl DensityMatrix {
+    fn mixed(polarization_count: usize) -> Self {
+        let n = 2 * polarization_count;
+        DensityMatrix {
+            real: DMatrix::<f64>::identity(n, n) / n as f64,
+            imag: DMatrix::<f64>::zeros(n, n),
+        }
+    }
+
+    fn polarization(&self) -> Vec<f64> {
+        let axes = self.real.nrows() / 2;
+        (0..axes)
+            .map(|axis| self.real[(axis, axis)] - self.real[(axis + axes, axis + axes)])
+            .collect()
+    }
+
+    fn add_scaled(&self, other: &DensityMatrix, factor: f64) -> DensityMatrix {
+        DensityMatrix {
+            real: &self.real + &other.real * factor,
+            imag: &self.imag + &other.imag * factor,
+        }
+    }
+
+    /// Time derivative from the von Neumann equation with relaxation towards
+    /// the `steady_state` density matrix, `dρ/dt = -i/ħ [H, ρ] - (ρ - ρ_ss) / τ`.
+    fn derivative(
+        &self,
+        hamiltonian: &DMatrix<f64>,
+        steady_state: &DMatrix<f64>,
+        relaxation_time: f64,
+    ) -> DensityMatrix {
+        let commutator_real = hamiltonian * &self.real - &self.real * hamiltonian;
+        let commutator_imag = hamiltonian * &self.imag - &self.imag * hamiltonian;
+        DensityMatrix {
+            real: commutator_imag / HBAR - (&self.real - steady_state) / relaxation_time,
+            imag: -commutator_real / HBAR - &self.imag / relaxation_time,
+        }
+    }
+
+    /// One classic Runge-Kutta step of `time_step` seconds.
+    fn step(
+        &self,
+        hamiltonian: &DMatrix<f64>,
+        steady_state: &DMatrix<f64>,
+        relaxation_time: f64,
+        time_step: f64,
+    ) -> DensityMatrix {
+        let k1 = self.derivative(hamiltonian, steady_state, relaxation_time);
+        let k2 = self.add_scaled(&k1, time_step / 2.0).derivative(
+            hamiltonian,
+            steady_state,
+            relaxation_time,
+        );
+        let k3 = self.add_scaled(&k2, time_step / 2.0).derivative(
+            hamiltonian,
+            steady_state,
+            relaxation_time,
+        );
+        let k4 =
+            self.add_scaled(&k3, time_step)
+                .derivative(hamiltonian, steady_state, relaxation_time);
+
+        self.add_scaled(&k1, time_step / 6.0)
+            .add_scaled(&k2, time_step / 3.0)
+            .add_scaled(&k3, time_step / 3.0)
+            .add_scaled(&k4, time_step / 6.0)
+    }
+}
+
+/// Cell Hamiltonian (in meV) for the field `Σ E_k P` of the neighbours on
+/// every polarization axis. The polarization states are coupled in a ring by
+/// the clock (tunneling) energy, as in the bistable cell response.
+fn cell_hamiltonian(field: &DVector<f64>, clock_energy: f64) -> DMatrix<f64> {
+    let axes = field.len();
+    let n = 2 * axes;
+    let mut hamiltonian = DMatrix::<f64>::zeros(n, n);
+    for i in 0..n {
+        hamiltonian[(i, i)] = if i < axes {
+            -field[i] / 2.0
+        } else {
+            field[i - axes] / 2.0
+        };
+        hamiltonian[(i, (i + 1) % n)] = -clock_energy;
+        hamiltonian[((i + 1) % n, i)] = -clock_energy;
+    }
+    hamiltonian
+}
+
+/// Thermal equilibrium density matrix of `hamiltonian`, the ground state
+/// projector for a thermal energy of 0.
+fn steady_state(hamiltonian: &DMatrix<f64>, thermal_energy: f64) -> DMatrix<f64> {
+    let decomposition = SymmetricEigen::new(hamiltonian.clone());
+    let eigenvalues = &decomposition.eigenvalues;
+    let weights = if thermal_energy > 0.0 {
+        let lowest = eigenvalues.min();
+        let weights = eigenvalues.map(|energy| (-(energy - lowest) / thermal_energy).exp());
+        let sum = weights.sum();
+        weights / sum
+    } else {
+        let mut weights = DVector::<f64>::zeros(eigenvalues.len());
+        weights[eigenvalues.imin()] = 1.0;
+        weights
+    };
+
+    let eigenvectors = &decomposition.eigenvectors;
+    eigenvectors * DMatrix::from_diagonal(&weights) * eigenvectors.transpose()
+}
+
+/// Time-dependent model integrating the equation of motion of every cell's
+/// coherence vector (density matrix) in real time. Each sample advances the
+/// simulation by the sample time, with the clock interpolated linearly
+/// between the previous and the current sample.
+pub struct CoherenceVectorModel {
+    clock_states: ClockStates,
+    previous_clock_states: Option<ClockStates>,
+    model_settings: CoherenceVectorModelSettings,
+    clock_generator_settings: ClockGeneratorSettings,
+    cell_ids: CellIds,
+    cells: Vec<QCACell>,
+    //Polarization of every cell
+    polarizations: CellStates,
+    //Density matrix of every dynamic cell, `None` for input and fixed cells
+    density_matrices: Vec<Option<DensityMatrix>>,
+    //Cell id and input index of every input cell
+    input_cells: Vec<(usize, usize)>,
+    neighborhood: Vec<Vec<BistableNeighbor>>,
+}
+
+impl CoherenceVectorModel {
+    /// Clock generator defaults of the coherence vector model, the clock
+    /// energies of QCADesigner in meV.
+    const CLOCK_GENERATOR_DEFAULTS: ClockGeneratorSettings = ClockGeneratorSettings {
+        amplitude_min: 0.2372,
+        amplitude_max: 6.117,
+        ..ClockGeneratorSettings::DEFAULT
+    };
+
+    pub fn new() -> Self {
+        CoherenceVectorModel {
+            clock_states: ClockStates::default(),
+            previous_clock_states: None,
+            model_settings: CoherenceVectorModelSettings::new(),
+            clock_generator_settings: Self::CLOCK_GENERATOR_DEFAULTS,
+            cell_ids: CellIds::new(&[]),
+            cells: vec![],
+            polarizations: CellStates::new(&[]),
+            density_matrices: vec![],
+            input_cells: vec![],
+            neighborhood: vec![],
+        }
+    }
+
+    /// Density matrix of a dynamic cell after one time step, with the
+    /// neighbour polarizations of the previous step, or `None` for fixed
+    /// cells.
+    fn step_cell(
+        &self,
+        cell_id: usize,
+        clock_states: &ClockStates,
+        time_step: f64,
+    ) -> Option<DensityMatrix> {
+        let density_matrix = self.density_matrices[cell_id].as_ref()?;
+        let cell = &self.cells[cell_id];
+
+        let mut field = DVector::<f64>::zeros(self.polarizations.current(cell_id).len());
+        for neighbour in &self.neighborhood[cell_id] {
+            field += &neighbour.kink_energy
+                * DVector::from_column_slice(self.polarizations.previous(neighbour.cell_id));
+        }
+
+        let clock_energy = clock_states.value(cell.clock_phase_shift).abs();
+        let hamiltonian = cell_hamiltonian(&field, clock_energy);
+        let steady_state = steady_state(&hamiltonian, K_B * self.model_settings.temperature);
+
+        Some(density_matrix.step(
+            &hamiltonian,
+            &steady_state,
+            self.model_settings.relaxation_time,
+            time_step,
+        ))
+    }
+}
+
+impl Default for CoherenceVectorModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulationModelTrait for CoherenceVectorModel {
+    fn get_name(&self) -> String {
+        "Coherence Vector".into()
+    }
+
+    fn get_unique_id(&self) -> String {
+        "coherence_vector".into()
+    }
+
+    fn get_model_settings(&self) -> Box<dyn SimulationModelSettingsTrait> {
+        Box::new(self.model_settings.clone()) as Box<dyn SimulationModelSettingsTrait>
+    }
+
+    fn get_clock_generator_settings(&self) -> Box<dyn ClockGeneratorSettingsTrait> {
+        Box::new(self.clock_generator_settings.clone()) as Box<dyn ClockGeneratorSettingsTrait>
+    }
+
+    fn get_model_options_list(&self) -> OptionsList {
+        vec![
+            OptionsEntry::Input {
+                unique_id: "time_step".into(),
+                name: "Time step".into(),
+                description: "Integration time step of the equations of motion".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("s".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "sample_time".into(),
+                name: "Sample time".into(),
+                description: "Simulated time between two samples".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("s".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "relaxation_time".into(),
+                name: "Relaxation time".into(),
+                description: "Time constant of the energy dissipation to the environment".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("s".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "temperature".into(),
+                name: "Temperature".into(),
+                description: "Temperature of the environment the cells relax towards".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("K".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "neighborhood_radius".into(),
+                name: "Radius of effect".into(),
+                description: "Radius of effect for neighbouring cells".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("nm".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "relative_permitivity".into(),
+                name: "Relative permittivity".into(),
+                description: "Relative permittivity of the medium".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: None,
+                    whole_num: false,
+                },
+            },
+        ]
+    }
+
+    fn get_clock_generator_options_list(&self) -> OptionsList {
+        clock_generator_options(true)
+    }
+
+    fn serialize_model_settings(&self) -> Result<String, String> {
+        serde_json::to_string(&self.model_settings).map_err(|err| err.to_string())
+    }
+
+    fn deserialize_model_settings(&mut self, settings_str: &String) -> Result<(), String> {
+        self.model_settings = serde_json::from_str::<CoherenceVectorModelSettings>(settings_str)
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn serialize_clock_generator_settings(&self) -> Result<String, String> {
+        serde_json::to_string(&self.clock_generator_settings).map_err(|err| err.to_string())
+    }
+
+    fn deserialize_clock_generator_settings(
+        &mut self,
+        settings_str: &String,
+    ) -> Result<(), String> {
+        self.clock_generator_settings =
+            ClockGeneratorSettings::from_json(settings_str, &Self::CLOCK_GENERATOR_DEFAULTS)?;
+        Ok(())
+    }
+
+    fn initiate(
+        &mut self,
+        layers: Box<Vec<QCALayer>>,
+        qca_architetures_map: HashMap<String, QCACellArchitecture>,
+    ) {
+        self.previous_clock_states = None;
+        self.cell_ids = CellIds::new(&layers);
+        self.cells = layers
+            .iter()
+            .flat_map(|layer| layer.cells.iter().cloned())
+            .collect();
+        self.polarizations = CellStates::new(
+            &self
+                .cells
+                .iter()
+                .map(|cell| {
+                    dot_probability_distribution_to_polarization(&cell.dot_probability_distribution)
+                })
+                .collect::<Vec<_>>(),
+        );
+        self.density_matrices = self
+            .cells
+            .iter()
+            .map(|cell| match cell.typ {
+                CellType::Normal | CellType::Output => Some(DensityMatrix::mixed(
+                    cell.dot_probability_distribution.len() / 4,
+                )),
+                CellType::Input | CellType::Fixed => None,
+            })
+            .collect();
+        // The mixed state is unpolarized
+        for (cell_id, density_matrix) in self.density_matrices.iter().enumerate() {
+            if let Some(density_matrix) = density_matrix {
+                self.polarizations
+                    .set_fixed(cell_id, &density_matrix.polarization());
+            }
+        }
+        self.input_cells = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.typ == CellType::Input)
+            .enumerate()
+            .map(|(input_index, (cell_id, _))| (cell_id, input_index))
+            .collect();
+
+        self.neighborhood = kink_energy_neighborhood(
+            &layers,
+            &qca_architetures_map,
+            &self.cell_ids,
+            Some(self.model_settings.neighborhood_radius),
+            self.model_settings.relative_permitivity,
+        );
+    }
+
+    fn pre_calculate(&mut self, clock_states: &ClockStates, input_states: &Vec<f64>) {
+        self.previous_clock_states = Some(match self.previous_clock_states {
+            Some(_) => std::mem::take(&mut self.clock_states),
+            None => clock_states.clone(),
+        });
+        self.clock_states = clock_states.clone();
+
+        for &(cell_id, input_index) in self.input_cells.iter() {
+            let polarization_count = self.polarizations.current(cell_id).len();
+            self.polarizations.set_fixed(
+                cell_id,
+                &input_states[(polarization_count * input_index)
+                    ..(polarization_count * input_index + polarization_count)],
+            );
+        }
+    }
+
+    /// Integrates a single cell over the sample time with all other cells
+    /// held fixed.
+    fn calculate(&mut self, cell_ind: QCACellIndex) -> f64 {
+        self.calculate_all(&[cell_ind], &WorkerPool::default())[0]
+    }
+
+    /// Integrates all cells over the sample time and returns the change of
+    /// their polarization during the sample.
+    fn calculate_all(&mut self, cell_indices: &[QCACellIndex], workers: &WorkerPool) -> Vec<f64> {
+        let cell_ids: Vec<usize> = cell_indices
+            .iter()
+            .map(|cell_ind| self.cell_ids.id(cell_ind))
+            .collect();
+        let start_polarizations: Vec<Vec<f64>> = cell_ids
+            .iter()
+            .map(|cell_id| self.polarizations.current(*cell_id).to_vec())
+            .collect();
+
+        let steps = (self.model_settings.sample_time / self.model_settings.time_step)
+            .ceil()
+            .max(1.0) as usize;
+        let time_step = self.model_settings.sample_time / steps as f64;
+        let previous_clock_states = self
+            .previous_clock_states
+            .clone()
+            .unwrap_or_else(|| self.clock_states.clone());
+
+        for step in 0..steps {
+            let progress = (step as f64 + 0.5) / steps as f64;
+            let clock_states = ClockStates {
+                phases: self.clock_states.phases.clone(),
+                values: previous_clock_states
+                    .values
+                    .iter()
+                    .zip(self.clock_states.values.iter())
+                    .map(|(previous, current)| previous + (current - previous) * progress)
+                    .collect(),
+            };
+
+            self.polarizations.advance();
+            let updates = workers.map(&cell_ids, |cell_id| {
+                self.step_cell(*cell_id, &clock_states, time_step)
+            });
+            for (cell_id, update) in cell_ids.iter().zip(updates) {
+                if let Some(density_matrix) = update {
+                    self.polarizations
+                        .set_current(*cell_id, &density_matrix.polarization());
+                    self.density_matrices[*cell_id] = Some(density_matrix);
+                }
+            }
+        }
+
+        cell_ids
+            .iter()
+            .zip(start_polarizations)
+            .map(|(cell_id, start)| {
+                self.polarizations
+                    .current(*cell_id)
+                    .iter()
+                    .zip(start)
+                    .map(|(new, old)| (new - old).abs())
+                    .fold(0.0, f64::max)
+            })
+            .collect()
+    }
+
+    fn get_states(&self, cell_ind: &QCACellIndex) -> Vec<f64> {
+        let polarization = self.polarizations.current(self.cell_ids.id(cell_ind));
+        // Integration errors may push the polarization slightly out of range
+        let sum = polarization.iter().map(|p| p.abs()).sum::<f64>().max(1.0);
+        polarization_to_dot_probability_distribution(
+            &polarization.iter().map(|p| p / sum).collect::<Vec<_>>(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::test_designs::wire;
+    use crate::simulation::{run_simulation, SimulationOptions};
+
+    /// Polarization a single cell relaxes to under a constant field.
+    fn relaxed_polarization(field: &[f64], clock_energy: f64, temperature: f64) -> Vec<f64> {
+        let hamiltonian = cell_hamiltonian(&DVector::from_column_slice(field), clock_energy);
+        let steady_state = steady_state(&hamiltonian, K_B * temperature);
+        let mut density_matrix = DensityMatrix::mixed(field.len());
+        for _ in 0..2_000 {
+            density_matrix = density_matrix.step(&hamiltonian, &steady_state, 1e-15, 1e-16);
+        }
+
+        let trace = density_matrix.real.trace();
+        assert!((trace - 1.0).abs() < 1e-9);
+        density_matrix.polarization()
+    }
+
+    #[test]
+    fn test_relaxes_to_two_state_response() {
+        // Zero temperature steady state of the two-state cell is `E / sqrt(1 + E²)`
+        // with `E = Σ E_k P / 2γ`
+        let polarization = relaxed_polarization(&[0.8], 0.5, 0.0);
+        assert!((polarization[0] - 0.8 / f64::sqrt(0.64 + 1.0)).abs() < 1e-6);
+
+        let warm = relaxed_polarization(&[0.8], 0.5, 5.0);
+        let expected =
+            0.8 / f64::sqrt(0.64 + 1.0) * f64::tanh(f64::sqrt(0.64 + 1.0) / 2.0 / (K_B * 5.0));
+        assert!((warm[0] - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_tri_state_polarization_stays_in_range() {
+        let polarization = relaxed_polarization(&[2.0, -1.5], 0.1, 0.0);
+        assert!(polarization[0] > 0.0 && polarization[1] < 0.0);
+        assert!(polarization.iter().map(|p| p.abs()).sum::<f64>() <= 1.0 + 1e-9);
+    }
+
+    #[test]
+    fn test_clocked_wire_switches() {
+        // The cells invert along the wire, so the output of three inverting
+        // hops is opposite to the input while the clock is low
+        let (layers, architectures) = wire(4);
+        let simulation = run_simulation(
+            Box::new(CoherenceVectorModel::new()),
+            layers,
+            architectures,
+            SimulationOptions::default(),
+        );
+        let clock = &simulation.clock_data[0];
+        let input = &simulation.cells_data[0].data;
+        let output = &simulation.cells_data[1].data;
+        let amplitude_min = clock.iter().map(|c| c.abs()).fold(f64::INFINITY, f64::min);
+        let amplitude_max = clock.iter().map(|c| c.abs()).fold(0.0, f64::max);
+
+        let held: Vec<usize> = (0..clock.len())
+            .filter(|i| clock[*i].abs() == amplitude_min)
+            .collect();
+        assert!(!held.is_empty());
+        for &i in held.iter() {
+            assert!(output[i] * -input[i].signum() > 0.5);
+        }
+        for i in (0..clock.len()).filter(|i| clock[*i].abs() == amplitude_max) {
+            assert!(output[i].abs() < 0.01);
+        }
+        assert!(input[held[0]] != input[*held.last().unwrap()]);
+
+        // The clock still falls during the first held sample, so its cells
+        // are less polarized than in the following one
+        assert!(output[held[0]].abs() < output[held[1]].abs() - 0.01);
+    }
+}
@@ -1,6 +1,12 @@
 use crate::design::file::QCADesign;
 use crate::get_qca_core_version;
 use crate::objects::cell::QCACellIndex;
+use crate::simulation::clock_generator::{
+    ClockNoise, ClockPhases, ClockSignal, DEFAULT_CLOCK_ZONE_COUNT,
+};
+use crate::simulation::input_generator::InputWaveform;
+use crate::simulation::stimulus::Stimulus;
+use crate::simulation::SampleTiming;
 use chrono::{DateTime, Local, TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
 use serde_inline_default::serde_inline_default;
@@ -13,9 +19,11 @@ pub const SIMULATION_FILE_EXTENSION: &str = "qcs";
 const DESIGN_ENTRY_NAME: &str = "DESIGN.json";
 const SIM_METADATA_ENTRY_NAME: &str = "METADATA.json";
 const SIM_DATA_ENTRY_NAME: &str = "DATA.bin";
+const SIM_CONVERGENCE_ENTRY_NAME: &str = "CONVERGENCE.json";
+const SIM_ERROR_PROBABILITY_ENTRY_NAME: &str = "ERROR_PROBABILITY.json";
 
-#[derive(Serialize, Deserialize, Debug)]
 #[serde_inline_default]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct QCASimulationMetadata {
     #[serde_inline_default("unknown".to_string())]
     pub qca_core_version: String,
@@ -24,19 +32,74 @@ pub struct QCASimulationMetadata {
     pub duration: TimeDelta,
     pub num_samples: usize,
 
+    /// Number of clock signals stored before the cell data, files written
+    /// without it use four clock zones.
+    #[serde_inline_default(DEFAULT_CLOCK_ZONE_COUNT)]
+    pub num_clock_zones: usize,
+
+    /// Phase shifts in degrees of the stored clock signals if the cells were
+    /// clocked at their exact phase instead of by zone.
+    #[serde_inline_default(None)]
+    pub clock_phase_shifts: Option<Vec<f64>>,
+
+    /// External clock signal the run used instead of the clock generator.
+    #[serde_inline_default(None)]
+    pub clock_signal: Option<ClockSignal>,
+
+    /// Random perturbations applied to the clock signals.
+    #[serde_inline_default(None)]
+    pub clock_noise: Option<ClockNoise>,
+
+    /// Shape of the input signals.
+    #[serde_inline_default(InputWaveform::default())]
+    pub input_waveform: InputWaveform,
+
+    /// Input sequence the run used instead of all input combinations.
+    #[serde_inline_default(None)]
+    pub stimulus: Option<Stimulus>,
+
+    /// Alignment of the inputs and the clock, unknown for files written
+    /// without it.
+    #[serde_inline_default(None)]
+    pub timing: Option<SampleTiming>,
+
     #[serde_inline_default(Vec::new())]
     pub stored_cells: Vec<QCACellIndex>,
 }
 
+/// Convergence of the relaxation loop of one sample.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QCASampleConvergence {
+    /// Number of iterations run for the sample.
+    pub iterations: usize,
+    /// Largest change of a cell state value in the last iteration.
+    pub max_residual: f64,
+    /// Whether the cells became stable before reaching the iteration limit.
+    pub converged: bool,
+}
+
 pub struct QCACellData {
     pub index: QCACellIndex,
     pub data: Vec<f64>,
 }
 
+/// Probability that an output cell was in a wrong state, per sample.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QCACellErrorProbability {
+    pub index: QCACellIndex,
+    pub data: Vec<f64>,
+}
+
 pub struct QCASimulationData {
     pub metadata: QCASimulationMetadata,
-    pub clock_data: [Vec<f64>; 4],
+    pub clock_data: Vec<Vec<f64>>,
     pub cells_data: Vec<QCACellData>,
+    /// Convergence of every sample, empty for files written without it and
+    /// for models that do not relax their samples.
+    pub convergence_data: Vec<QCASampleConvergence>,
+    /// Error probability of every output cell, empty for models that do not
+    /// estimate it.
+    pub error_probabilities: Vec<QCACellErrorProbability>,
 }
 
 impl QCACellData {
@@ -55,16 +118,39 @@ impl QCASimulationMetadata {
             start_time: Local::now(),
             duration: TimeDelta::zero(),
             num_samples: 0,
+            num_clock_zones: DEFAULT_CLOCK_ZONE_COUNT,
+            clock_phase_shifts: None,
+            clock_signal: None,
+            clock_noise: None,
+            input_waveform: InputWaveform::default(),
+            stimulus: None,
+            timing: None,
             stored_cells: Vec::new(),
         }
     }
 }
 
+impl QCASimulationMetadata {
+    /// Assignment of the cells to the stored clock signals
+    pub fn clock_phases(&self) -> ClockPhases {
+        match &self.clock_phase_shifts {
+            Some(phase_shifts) => ClockPhases::Continuous {
+                phase_shifts: phase_shifts.clone(),
+            },
+            None => ClockPhases::Zones {
+                count: self.num_clock_zones,
+            },
+        }
+    }
+}
+
 impl QCASimulationData {
     pub fn new() -> QCASimulationData {
         QCASimulationData {
-            clock_data: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            clock_data: vec![Vec::new(); DEFAULT_CLOCK_ZONE_COUNT],
             cells_data: Vec::new(),
+            convergence_data: Vec::new(),
+            error_probabilities: Vec::new(),
             metadata: QCASimulationMetadata::new(),
         }
     }
@@ -122,16 +208,19 @@ fn read_sim_stream(
     let num_samples = simulation_data.metadata.num_samples;
     let mut data_off: usize = 0;
 
-    for i in 0..simulation_data.clock_data.len() {
-        simulation_data.clock_data[i] = Vec::with_capacity(num_samples);
-        for _ in 0..num_samples {
-            let value = f64::from_ne_bytes(
-                <[u8; 8]>::try_from(&data[data_off..data_off + size_of::<f64>()]).unwrap(),
-            );
-            data_off += size_of::<f64>();
-            simulation_data.clock_data[i].push(value);
-        }
-    }
+    simulation_data.clock_data = (0..simulation_data.metadata.num_clock_zones)
+        .map(|_| {
+            let mut clock_data = Vec::with_capacity(num_samples);
+            for _ in 0..num_samples {
+                let value = f64::from_ne_bytes(
+                    <[u8; 8]>::try_from(&data[data_off..data_off + size_of::<f64>()]).unwrap(),
+                );
+                data_off += size_of::<f64>();
+                clock_data.push(value);
+            }
+            clock_data
+        })
+        .collect();
 
     simulation_data.cells_data = simulation_data
         .metadata
@@ -175,6 +264,22 @@ pub fn write_to_file(
     let sim_data_raw = get_sim_data_raw(simulation_data);
     write_slice(&mut builder, SIM_DATA_ENTRY_NAME, sim_data_raw)?;
 
+    let sim_convergence_raw =
+        serde_json::to_vec(&simulation_data.convergence_data).map_err(|error| error.to_string())?;
+    write_slice(
+        &mut builder,
+        SIM_CONVERGENCE_ENTRY_NAME,
+        sim_convergence_raw,
+    )?;
+
+    let sim_error_probability_raw = serde_json::to_vec(&simulation_data.error_probabilities)
+        .map_err(|error| error.to_string())?;
+    write_slice(
+        &mut builder,
+        SIM_ERROR_PROBABILITY_ENTRY_NAME,
+        sim_error_probability_raw,
+    )?;
+
     builder.into_inner().map_err(|error| error.to_string())?;
 
     Ok(())
@@ -187,6 +292,8 @@ pub fn read_from_file(file: File) -> Result<(QCADesign, QCASimulationData), Stri
     let mut design: Option<QCADesign> = None;
     let mut metadata: Option<QCASimulationMetadata> = None;
     let mut sim_data: Option<Vec<u8>> = None;
+    let mut convergence_data: Vec<QCASampleConvergence> = Vec::new();
+    let mut error_probabilities: Vec<QCACellErrorProbability> = Vec::new();
 
     for entry in entries {
         let mut entry = entry.map_err(|error| error.to_string())?;
@@ -219,6 +326,24 @@ pub fn read_from_file(file: File) -> Result<(QCADesign, QCASimulationData), Stri
                     .map_err(|error| error.to_string())?;
                 sim_data = Some(contents);
             }
+            SIM_CONVERGENCE_ENTRY_NAME => {
+                let mut contents = String::new();
+                let _ = entry
+                    .read_to_string(&mut contents)
+                    .map_err(|error| error.to_string())?;
+                convergence_data =
+                    serde_json::from_str::<Vec<QCASampleConvergence>>(contents.as_str())
+                        .map_err(|error| error.to_string())?;
+            }
+            SIM_ERROR_PROBABILITY_ENTRY_NAME => {
+                let mut contents = String::new();
+                let _ = entry
+                    .read_to_string(&mut contents)
+                    .map_err(|error| error.to_string())?;
+                error_probabilities =
+                    serde_json::from_str::<Vec<QCACellErrorProbability>>(contents.as_str())
+                        .map_err(|error| error.to_string())?;
+            }
             _ => {}
         }
     }
@@ -228,6 +353,8 @@ pub fn read_from_file(file: File) -> Result<(QCADesign, QCASimulationData), Stri
             if let Some(sim_data) = sim_data {
                 let mut simulation = QCASimulationData::new();
                 simulation.metadata = metadata;
+                simulation.convergence_data = convergence_data;
+                simulation.error_probabilities = error_probabilities;
                 read_sim_stream(&mut simulation, &design, sim_data)
                     .map_err(|error| error.to_string())?;
                 Ok((design, simulation))
@@ -0,0 +1,364 @@
+use nalgebra::{Cholesky, DMatrix, DVector, Schur};
+use serde::{Deserialize, Serialize};
+
+/// Method used to find the ground state of a cell Hamiltonian.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ICHAEigenSolver {
+    /// Full Schur decomposition of the Hamiltonian.
+    Schur,
+    /// Symmetric eigenvalue decomposition followed by inverse iteration for
+    /// the ground state.
+    Symmetric,
+    /// Lanczos iteration for the lowest eigenpair only.
+    Lanczos,
+}
+
+impl ICHAEigenSolver {
+    /// Eigenvector of the lowest eigenvalue of the symmetric `hamiltonian`, or
+    /// `None` if the solver did not converge. `initial` is only used by the
+    /// iterative solvers as the starting vector.
+    pub fn ground_state(
+        &self,
+        hamiltonian: &DMatrix<f64>,
+        initial: &DVector<f64>,
+        eps: f64,
+        max_iterations: usize,
+    ) -> Option<DVector<f64>> {
+        match self {
+            ICHAEigenSolver::Schur => {
+                let decomposition = Schur::try_new(hamiltonian.clone(), eps, max_iterations)?;
+                let eigenvalues = decomposition.eigenvalues()?;
+                let index = eigenvalues.imin();
+                Some(decomposition.unpack().0.column(index).into_owned())
+            }
+            ICHAEigenSolver::Symmetric => {
+                // nalgebra's symmetric decomposition finds the right
+                // eigenvalues, but for some of these matrices it returns two
+                // of the eigenvectors swapped, so the column of the lowest
+                // eigenvalue can be an excited state. The ground state is
+                // recovered by inverse iteration instead.
+                let lowest = hamiltonian.symmetric_eigenvalues().min();
+                inverse_iteration(hamiltonian, lowest, initial, eps, max_iterations)
+            }
+            ICHAEigenSolver::Lanczos => lanczos(hamiltonian, initial, eps, max_iterations),
+        }
+    }
+}
+
+/// Probability of every basis state in the thermal (Boltzmann weighted)
+/// mixture of all eigenstates of the symmetric blocks `hamiltonians` of a
+/// block diagonal Hamiltonian, per block, together with the block and the
+/// vector of its ground state. `thermal_energy` is k_B·T in the units of the
+/// Hamiltonian. All eigenpairs are taken from full Schur decompositions.
+pub fn thermal_state_probabilities(
+    hamiltonians: &[DMatrix<f64>],
+    thermal_energy: f64,
+    eps: f64,
+    max_iterations: usize,
+) -> Option<(Vec<DVector<f64>>, usize, DVector<f64>)> {
+    let decompositions = hamiltonians
+        .iter()
+        .map(|hamiltonian| {
+            let decomposition = Schur::try_new(hamiltonian.clone(), eps, max_iterations)?;
+            let eigenvalues = decomposition.eigenvalues()?;
+            Some((eigenvalues, decomposition.unpack().0))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let (ground_block, lowest) = decompositions
+        .iter()
+        .map(|(eigenvalues, _)| eigenvalues.min())
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    let weights: Vec<DVector<f64>> = decompositions
+        .iter()
+        .map(|(eigenvalues, _)| {
+            eigenvalues.map(|energy| (-(energy - lowest) / thermal_energy).exp())
+        })
+        .collect();
+    let total_weight: f64 = weights.iter().map(|weights| weights.sum()).sum();
+    let probabilities = decompositions
+        .iter()
+        .zip(weights.iter())
+        .map(|((_, eigenvectors), weights)| {
+            eigenvectors.map(|value| value.powi(2)) * weights / total_weight
+        })
+        .collect();
+
+    let (eigenvalues, eigenvectors) = &decompositions[ground_block];
+    Some((
+        probabilities,
+        ground_block,
+        eigenvectors.column(eigenvalues.imin()).into_owned(),
+    ))
+}
+
+/// Number of Lanczos steps between convergence checks of the Ritz pair.
+const LANCZOS_CHECK_INTERVAL: usize = 4;
+
+/// Normalized `initial` mixed with a fixed asymmetric vector. The uniform
+/// vector used for fresh cells is orthogonal to ground states of other
+/// symmetry, which the iterative solvers would otherwise never find. A zero
+/// `initial` only keeps the fixed vector.
+fn starting_vector(initial: &DVector<f64>) -> Option<DVector<f64>> {
+    let perturbation = DVector::<f64>::from_fn(initial.len(), |i, _| (i as f64 + 1.0).sin());
+    let initial = initial
+        .try_normalize(0.0)
+        .unwrap_or_else(|| DVector::zeros(initial.len()));
+    (initial + perturbation.normalize()).try_normalize(0.0)
+}
+
+/// Inverse iteration for the eigenvector of `eigenvalue`, which must be the
+/// lowest one so that the slightly lower shift keeps the matrix positive
+/// definite.
+fn inverse_iteration(
+    hamiltonian: &DMatrix<f64>,
+    eigenvalue: f64,
+    initial: &DVector<f64>,
+    eps: f64,
+    max_iterations: usize,
+) -> Option<DVector<f64>> {
+    let dim = hamiltonian.nrows();
+    let shift = eigenvalue - 1e-9 * hamiltonian.amax().max(1.0);
+    let cholesky = Cholesky::new(hamiltonian - DMatrix::<f64>::identity(dim, dim) * shift)?;
+
+    let mut vector = starting_vector(initial)?;
+    for _ in 0..max_iterations {
+        let next = cholesky.solve(&vector).normalize();
+        let change = (&next - &vector).norm().min((&next + &vector).norm());
+        vector = next;
+        if change < eps {
+            return Some(vector);
+        }
+    }
+
+    None
+}
+
+/// Lanczos iteration with full reorthogonalization. Stops once the residual
+/// norm of the lowest Ritz pair drops below `eps`.
+fn lanczos(
+    hamiltonian: &DMatrix<f64>,
+    initial: &DVector<f64>,
+    eps: f64,
+    max_iterations: usize,
+) -> Option<DVector<f64>> {
+    let dim = hamiltonian.nrows();
+    let mut basis: Vec<DVector<f64>> = vec![starting_vector(initial)?];
+    let mut alpha: Vec<f64> = vec![];
+    let mut beta: Vec<f64> = vec![];
+
+    for j in 0..dim.min(max_iterations) {
+        let mut w = hamiltonian * &basis[j];
+        alpha.push(basis[j].dot(&w));
+        // Orthogonalizing twice keeps the basis orthogonal to working
+        // precision even when most of `w` cancels.
+        for _ in 0..2 {
+            for q in basis.iter() {
+                let overlap = q.dot(&w);
+                w.axpy(-overlap, q, 1.0);
+            }
+        }
+        let residual = w.norm();
+
+        let k = alpha.len();
+        if k.is_multiple_of(LANCZOS_CHECK_INTERVAL) || residual < eps || k == dim {
+            let tridiagonal = DMatrix::<f64>::from_fn(k, k, |r, c| match r.abs_diff(c) {
+                0 => alpha[r],
+                1 => beta[r.min(c)],
+                _ => 0.0,
+            });
+            let lowest = tridiagonal.symmetric_eigenvalues().min();
+            let coefficients = inverse_iteration(
+                &tridiagonal,
+                lowest,
+                &DVector::<f64>::from_element(k, 1.0),
+                eps,
+                max_iterations,
+            )?;
+
+            if residual * coefficients[k - 1].abs() < eps || residual < eps || k == dim {
+                let mut ground_state = DVector::<f64>::zeros(dim);
+                for (q, s) in basis.iter().zip(coefficients.iter()) {
+                    ground_state.axpy(*s, q, 1.0);
+                }
+                return Some(ground_state.normalize());
+            }
+        }
+
+        beta.push(residual);
+        basis.push(w / residual);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::QCACellInternal;
+    use super::*;
+    use crate::objects::architecture::QCACellArchitecture;
+    use crate::objects::cell::{CellType, QCACell};
+    use crate::objects::layer::QCALayer;
+
+    /// Full Hamiltonian and occupation matrix of a tri-state cell, and its
+    /// exchange symmetry blocks
+    fn cell_hamiltonian(
+        clock: f64,
+        potential: &[f64],
+    ) -> (DMatrix<f64>, DMatrix<f64>, Vec<DMatrix<f64>>) {
+        let mut architecture = QCACellArchitecture::new(60.0, 10.0, 8, 17.420839531685022);
+        architecture.dot_tunnels = (0..8).map(|i| (i, (i + 1) % 8)).collect();
+        let layer = QCALayer::new("Main Layer".into(), "tri_state_60".into(), 0.0);
+        let cell = QCACell {
+            position: [0.0, 0.0],
+            rotation: 0.0,
+            typ: CellType::Normal,
+            clock_phase_shift: 0.0,
+            dot_probability_distribution: vec![0.25; 8],
+            label: None,
+        };
+        let cell = Box::new(cell);
+        let (static_hamilton_matrix, dynamic_hamilton_matrix, occupation_matrix) =
+            QCACellInternal::generate_hamilton_matrices(&cell, &layer, &architecture, 12.9);
+        let internal = QCACellInternal::new(cell, &layer, &architecture, 12.9);
+
+        let potential = DVector::<f64>::from_column_slice(potential);
+        let mut hamiltonian = static_hamilton_matrix + dynamic_hamilton_matrix * clock;
+        let potential_energy = &occupation_matrix * &potential;
+        for i in 0..hamiltonian.nrows() {
+            hamiltonian[(i, i)] += potential_energy[i];
+        }
+        let blocks = internal
+            .exchange_blocks
+            .iter()
+            .map(|block| block.hamilton_matrix(clock, &potential))
+            .collect();
+        (hamiltonian, occupation_matrix, blocks)
+    }
+
+    /// Dot potentials of a cell of examples/line.qcd for which nalgebra's
+    /// symmetric decomposition swaps the eigenvectors of the lowest eigenvalue
+    /// and an excited one at clocks -1.309 and -2
+    const SWAPPED_EIGENVECTORS_POTENTIAL: [f64; 8] = [
+        -0.004554917350558435,
+        0.03808163007170436,
+        0.1087338541444042,
+        0.19138469279996523,
+        -0.08732572704802923,
+        -0.2353102457203536,
+        -0.1194401530503737,
+        -0.047064210750293656,
+    ];
+
+    #[test]
+    fn test_symmetric_solver_with_swapped_eigenvectors() {
+        // The lowest eigenvalue is well separated, so the issue is not the
+        // ambiguity of a degenerate subspace: the returned eigenvectors span
+        // the right eigenspaces, but are paired with the wrong eigenvalues.
+        for clock in [-1.3090169943749475, -2.0] {
+            let (h, _, _) = cell_hamiltonian(clock, &SWAPPED_EIGENVECTORS_POTENTIAL);
+            let mut eigenvalues: Vec<f64> = h.symmetric_eigenvalues().iter().cloned().collect();
+            eigenvalues.sort_by(f64::total_cmp);
+            assert!(eigenvalues[1] - eigenvalues[0] > 0.25);
+
+            let initial = DVector::<f64>::from_element(h.nrows(), 1.0);
+            let state = ICHAEigenSolver::Symmetric
+                .ground_state(&h, &initial, 1e-6, 1_000)
+                .unwrap();
+            assert!((&h * &state - &state * eigenvalues[0]).norm() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_solvers_agree_on_ground_state() {
+        // Without an external potential the ground state of a weakly clocked
+        // cell is degenerate, so only polarizing potentials are compared
+        let potentials = [
+            [0.4, 0.1, -0.2, -0.5, -0.4, -0.1, 0.2, 0.5],
+            [1.2, 0.9, 0.3, -0.1, -0.3, 0.1, 0.6, 1.0],
+            SWAPPED_EIGENVECTORS_POTENTIAL,
+        ];
+        for clock in [-0.2, -1.3090169943749475, -2.0] {
+            for potential in potentials.iter() {
+                let (h, occupation, _) = cell_hamiltonian(clock, potential);
+                let lowest = h.symmetric_eigenvalues().min();
+                let initial = DVector::<f64>::from_element(h.nrows(), 1.0);
+
+                let charges: Vec<DVector<f64>> = [
+                    ICHAEigenSolver::Schur,
+                    ICHAEigenSolver::Symmetric,
+                    ICHAEigenSolver::Lanczos,
+                ]
+                .iter()
+                .map(|solver| {
+                    let state = solver.ground_state(&h, &initial, 1e-6, 1_000).unwrap();
+                    assert!((state.norm() - 1.0).abs() < 1e-9);
+                    assert!((state.dot(&(&h * &state)) - lowest).abs() < 1e-9);
+                    occupation.tr_mul(&state.map(|value| value.powi(2)))
+                })
+                .collect();
+
+                for charge in charges.iter() {
+                    assert!((charge - &charges[0]).amax() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_exchange_blocks_match_full_hamiltonian() {
+        let (h, _, blocks) = cell_hamiltonian(-0.2, &[0.4, 0.1, -0.2, -0.5, -0.4, -0.1, 0.2, 0.5]);
+        assert_eq!(
+            blocks.iter().map(|block| block.nrows()).sum::<usize>(),
+            h.nrows()
+        );
+
+        // The blocks hold the full spectrum of the cell Hamiltonian
+        let mut full: Vec<f64> = h.symmetric_eigenvalues().iter().cloned().collect();
+        let mut blocked: Vec<f64> = blocks
+            .iter()
+            .flat_map(|block| {
+                block
+                    .symmetric_eigenvalues()
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        full.sort_by(f64::total_cmp);
+        blocked.sort_by(f64::total_cmp);
+        for (a, b) in full.iter().zip(blocked.iter()) {
+            assert!((a - b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_thermal_state_limits() {
+        let (h, _, _) = cell_hamiltonian(-2.0, &[0.4, 0.1, -0.2, -0.5, -0.4, -0.1, 0.2, 0.5]);
+        let ground_state = ICHAEigenSolver::Schur
+            .ground_state(
+                &h,
+                &DVector::<f64>::from_element(h.nrows(), 1.0),
+                1e-6,
+                1_000,
+            )
+            .unwrap();
+
+        // Close to 0 K only the ground state is occupied
+        let (cold, block, cold_ground_state) =
+            thermal_state_probabilities(&[h.clone()], 1e-6, 1e-6, 1_000).unwrap();
+        assert_eq!(block, 0);
+        assert!((&cold[0] - ground_state.map(|value| value.powi(2))).amax() < 1e-6);
+        assert!((cold_ground_state.map(|value| value.powi(2)) - &cold[0]).amax() < 1e-6);
+
+        // At high temperatures all eigenstates are equally occupied
+        let (hot, _, _) = thermal_state_probabilities(&[h.clone()], 1e9, 1e-6, 1_000).unwrap();
+        let hot = &hot[0];
+        assert!((hot.sum() - 1.0).abs() < 1e-9);
+        assert!(hot
+            .iter()
+            .all(|p| (p - 1.0 / h.nrows() as f64).abs() < 1e-6));
+    }
+}
@@ -1,12 +1,23 @@
+mod eigen_solver;
+
+pub use eigen_solver::ICHAEigenSolver;
+
+use eigen_solver::thermal_state_probabilities;
+
 use super::{CellType, QCACellArchitecture, QCACellIndex, QCALayer, SimulationModelTrait};
 use crate::objects::cell::{polarization_to_dot_probability_distribution, QCACell};
-use crate::simulation::model::{ClockGeneratorSettingsTrait, SimulationModelSettingsTrait};
-use crate::simulation::settings::{InputDescriptor, OptionsEntry, OptionsList};
-use nalgebra::{distance, DMatrix, DMatrixView, DVector, DVectorView, Point3, Schur};
+use crate::simulation::cell_state::{CellIds, CellStates};
+use crate::simulation::clock_generator::ClockStates;
+use crate::simulation::model::{
+    clock_generator_options, update_scheme_options, ClockGeneratorSettings,
+    ClockGeneratorSettingsTrait, SimulationModelSettingsTrait, UpdateScheme, WorkerPool, K_B,
+};
+use crate::simulation::neighborhood::NeighborhoodIndex;
+use crate::simulation::settings::{InputDescriptor, OptionsEntry, OptionsList, SelectOption};
+use nalgebra::{distance, DMatrix, DMatrixView, DVector, DVectorView, Point3};
 use serde::{Deserialize, Serialize};
 use serde_inline_default::serde_inline_default;
 use std::collections::HashMap;
-use std::mem;
 
 const E_CHARGE: f64 = 1.602_176_634e-19; // Coulombs [C]
 const EPS_0: f64 = 8.854_187_8128e-12; // F/m [C^2 / N·m^2]
@@ -23,18 +34,89 @@ pub struct QCACellInternal {
     cell: Box<QCACell>,
     _z_position: f64,
 
-    //The full hamilton matrix
-    hamilton_matrix: DMatrix<f64>,
+    //Hamiltonian in the full basis
+    hamiltonian: ICHAHamiltonBlock,
+    //Hamiltonian blocks of the electron exchange symmetric and antisymmetric
+    //states
+    exchange_blocks: Vec<ICHAHamiltonBlock>,
+}
+
+/// Cell Hamiltonian restricted to the span of a set of basis vectors. The
+/// Hamiltonian does not couple the states that are symmetric and
+/// antisymmetric under exchange of the dots of the two electrons, so the two
+/// blocks can be solved on their own, which is several times cheaper than
+/// decomposing the full matrix.
+#[derive(Debug, Clone)]
+struct ICHAHamiltonBlock {
+    //Block basis vectors (columns) in the full basis
+    basis: DMatrix<f64>,
     //Static hamilton matrix (1, 3, 4)
     static_hamilton_matrix: DMatrix<f64>,
     //Dynamic hamilton matrix (2)
     dynamic_hamilton_matrix: DMatrix<f64>,
+    //Number of electrons at each dot (columns) for every block state (rows)
+    occupation_matrix: DMatrix<f64>,
+}
 
-    //Potential energy at each dot
-    dot_potential: DVector<f64>,
-    dot_charge_probability: DVector<f64>,
+impl ICHAHamiltonBlock {
+    fn new(
+        basis: DMatrix<f64>,
+        static_hamilton_matrix: &DMatrix<f64>,
+        dynamic_hamilton_matrix: &DMatrix<f64>,
+        occupation_matrix: &DMatrix<f64>,
+    ) -> Self {
+        ICHAHamiltonBlock {
+            static_hamilton_matrix: basis.transpose() * static_hamilton_matrix * &basis,
+            dynamic_hamilton_matrix: basis.transpose() * dynamic_hamilton_matrix * &basis,
+            // Both exchanged states have the same occupation
+            occupation_matrix: basis.map(|value| value.powi(2)).transpose() * occupation_matrix,
+            basis,
+        }
+    }
+
+    /// Symmetric and antisymmetric basis under exchange of the electron dots,
+    /// where basis state `n * a + b` is exchanged with `n * b + a`.
+    fn exchange_bases(dot_count: usize) -> [DMatrix<f64>; 2] {
+        let n = dot_count;
+        let pairs = |strict: bool| {
+            (0..n)
+                .flat_map(move |a| (a..n).map(move |b| (a, b)))
+                .filter(move |(a, b)| !strict || a != b)
+                .collect::<Vec<_>>()
+        };
+        let basis = |pairs: Vec<(usize, usize)>, sign: f64| {
+            let mut basis = DMatrix::<f64>::zeros(n * n, pairs.len());
+            for (column, (a, b)) in pairs.into_iter().enumerate() {
+                if a == b {
+                    basis[(n * a + b, column)] = 1.0;
+                } else {
+                    basis[(n * a + b, column)] = std::f64::consts::FRAC_1_SQRT_2;
+                    basis[(n * b + a, column)] = sign * std::f64::consts::FRAC_1_SQRT_2;
+                }
+            }
+            basis
+        };
+        [basis(pairs(false), 1.0), basis(pairs(true), -1.0)]
+    }
+
+    /// Block Hamiltonian at a clock value and dot potential
+    fn hamilton_matrix(&self, clock_value: f64, dot_potential: &DVector<f64>) -> DMatrix<f64> {
+        let mut hamilton_matrix =
+            &self.static_hamilton_matrix + &self.dynamic_hamilton_matrix * clock_value;
+        let potential_energy = &self.occupation_matrix * dot_potential;
+        for i in 0..hamilton_matrix.nrows() {
+            hamilton_matrix[(i, i)] += potential_energy[i];
+        }
+        hamilton_matrix
+    }
+}
 
-    basis_matrix: DMatrix<f64>,
+/// Precomputed electrostatic coupling between the dots of a cell and the dots
+/// of one of its neighbours, `Vq / r` for every dot pair.
+#[derive(Debug, Clone)]
+struct ICHACoupling {
+    cell_id: usize,
+    coefficients: DMatrix<f64>,
 }
 
 impl QCACellInternal {
@@ -44,6 +126,42 @@ impl QCACellInternal {
         cell_architecture: &QCACellArchitecture,
         relative_permittivity: f64,
     ) -> Self {
+        let n: usize = cell_architecture.dot_count as usize;
+        let (static_hamilton_matrix, dynamic_hamilton_matrix, occupation_matrix) =
+            Self::generate_hamilton_matrices(
+                &cell,
+                layer,
+                cell_architecture,
+                relative_permittivity,
+            );
+        let block = |basis: DMatrix<f64>| {
+            ICHAHamiltonBlock::new(
+                basis,
+                &static_hamilton_matrix,
+                &dynamic_hamilton_matrix,
+                &occupation_matrix,
+            )
+        };
+
+        QCACellInternal {
+            cell: cell,
+            _z_position: layer.z_position,
+            hamiltonian: block(DMatrix::identity(n * n, n * n)),
+            exchange_blocks: ICHAHamiltonBlock::exchange_bases(n)
+                .into_iter()
+                .map(block)
+                .collect(),
+        }
+    }
+
+    /// Static and dynamic Hamiltonian and occupation matrix of a cell in the
+    /// full basis of dot pairs.
+    fn generate_hamilton_matrices(
+        cell: &QCACell,
+        layer: &QCALayer,
+        cell_architecture: &QCACellArchitecture,
+        relative_permittivity: f64,
+    ) -> (DMatrix<f64>, DMatrix<f64>, DMatrix<f64>) {
         let n: usize = cell_architecture.dot_count as usize;
         let tunneling_matrix = Self::generate_tunneling_matrix(&cell_architecture, 1.0);
         let basis_matrix = Self::generate_basis_matrix(n);
@@ -71,9 +189,9 @@ impl QCACellInternal {
                                 basis_matrix.row(i).transpose().as_view(),
                                 basis_matrix.row(j).transpose().as_view(),
                             ) + Self::hamilton_term_4(
-                                &cell,
-                                &layer,
-                                &cell_architecture,
+                                cell,
+                                layer,
+                                cell_architecture,
                                 vq,
                                 basis_matrix.row(i).transpose().as_view(),
                                 basis_matrix.row(j).transpose().as_view(),
@@ -103,21 +221,24 @@ impl QCACellInternal {
                 .flatten(),
         );
 
-        QCACellInternal {
-            cell: cell,
-            _z_position: layer.z_position,
-            hamilton_matrix: &static_hamilton_matrix + &dynamic_hamilton_matrix,
-            static_hamilton_matrix: static_hamilton_matrix,
-            dynamic_hamilton_matrix: dynamic_hamilton_matrix,
-            dot_potential: dot_potential,
-            dot_charge_probability: DVector::<f64>::from_vec(vec![2.0 / n as f64; n]),
-            basis_matrix: basis_matrix,
-        }
+        let occupation_matrix = DMatrix::<f64>::from_fn(n * n, n, |i, dot| {
+            (0..=1)
+                .map(|spin| {
+                    Self::count_operator(n, dot, spin, basis_matrix.row(i).transpose().as_view())
+                })
+                .sum()
+        });
+
+        (
+            static_hamilton_matrix,
+            dynamic_hamilton_matrix,
+            occupation_matrix,
+        )
     }
 
     fn get_dot_position(
         dot_index: usize,
-        cell: &Box<QCACell>,
+        cell: &QCACell,
         layer: &QCALayer,
         cell_architecture: &QCACellArchitecture,
     ) -> Point3<f64> {
@@ -322,7 +443,7 @@ impl QCACellInternal {
     }
 
     fn hamilton_term_4(
-        cell: &Box<QCACell>,
+        cell: &QCACell,
         layer: &QCALayer,
         cell_architecture: &QCACellArchitecture,
         vq: f64,
@@ -378,46 +499,48 @@ pub struct ICHAModelSettings {
     #[serde_inline_default(1e-6)]
     convergence_tolerance: f64,
 
-    #[serde_inline_default(12.9)]
-    relative_permitivity: f64,
+    #[serde_inline_default(UpdateScheme::GaussSeidel)]
+    update_scheme: UpdateScheme,
 
-    #[serde_inline_default(1_000)]
-    schur_max_iterations: usize,
+    #[serde_inline_default(0.5)]
+    damping_factor: f64,
 
-    #[serde_inline_default(1e-6)]
-    schur_convergence_tolerance: f64,
-}
+    #[serde_inline_default(12.9)]
+    relative_permitivity: f64,
 
-#[serde_inline_default]
-#[derive(Serialize, Deserialize, Clone)]
-pub struct ICHAClockGeneratorSettings {
-    #[serde_inline_default(1)]
-    num_cycles: usize,
+    #[serde_inline_default(0.0)]
+    neighborhood_radius: f64,
 
-    #[serde_inline_default(0.0000237177)]
-    amplitude_min: f64,
+    #[serde_inline_default(0.0)]
+    temperature: f64,
 
-    #[serde_inline_default(2.0)]
-    amplitude_max: f64,
+    #[serde_inline_default(ICHAEigenSolver::Schur)]
+    eigen_solver: ICHAEigenSolver,
 
-    #[serde_inline_default(0)]
-    extra_periods: usize,
+    #[serde_inline_default(1_000)]
+    #[serde(alias = "schur_max_iterations")]
+    solver_max_iterations: usize,
 
-    #[serde_inline_default(20)]
-    samples_per_input: usize,
+    #[serde_inline_default(1e-6)]
+    #[serde(alias = "schur_convergence_tolerance")]
+    solver_convergence_tolerance: f64,
 }
 
 pub struct ICHAModel {
-    clock_states: [f64; 4],
+    clock_states: ClockStates,
     input_states: Vec<f64>,
     model_settings: ICHAModelSettings,
-    clock_generator_settings: ICHAClockGeneratorSettings,
-    layer_map: HashMap<usize, QCALayer>,
-    cell_architectures_map: HashMap<String, QCACellArchitecture>,
-    cell_input_map: HashMap<QCACellIndex, usize>,
-    index_cells_static_map: HashMap<QCACellIndex, QCACellInternal>,
-    index_cells_read_map: HashMap<QCACellIndex, QCACellInternal>,
-    index_cells_write_map: HashMap<QCACellIndex, QCACellInternal>,
+    clock_generator_settings: ClockGeneratorSettings,
+    cell_ids: CellIds,
+    //Dynamic cells, `None` for input and fixed cells
+    cells: Vec<Option<QCACellInternal>>,
+    //Dot charge probability of every cell
+    states: CellStates,
+    //Last ground state of every dynamic cell, starting vector for iterative eigen solvers
+    ground_states: Vec<DVector<f64>>,
+    //Cell id and input index of every input cell
+    input_cells: Vec<(usize, usize)>,
+    couplings: Vec<Vec<ICHACoupling>>,
 }
 
 impl ICHAModelSettings {
@@ -426,12 +549,6 @@ impl ICHAModelSettings {
     }
 }
 
-impl ICHAClockGeneratorSettings {
-    pub fn new() -> Self {
-        serde_json::from_str::<ICHAClockGeneratorSettings>("{}".into()).unwrap()
-    }
-}
-
 impl SimulationModelSettingsTrait for ICHAModelSettings {
     fn get_max_iterations(&self) -> usize {
         self.max_iterations
@@ -439,41 +556,163 @@ impl SimulationModelSettingsTrait for ICHAModelSettings {
     fn get_convergence_tolerance(&self) -> f64 {
         self.convergence_tolerance
     }
-}
-
-impl ClockGeneratorSettingsTrait for ICHAClockGeneratorSettings {
-    fn get_num_cycles(&self) -> usize {
-        self.num_cycles
-    }
-    fn get_amplitude_min(&self) -> f64 {
-        self.amplitude_min
-    }
-    fn get_amplitude_max(&self) -> f64 {
-        self.amplitude_max
-    }
-    fn get_extra_periods(&self) -> usize {
-        self.extra_periods
+    fn get_update_scheme(&self) -> UpdateScheme {
+        self.update_scheme
     }
-    fn get_samples_per_input(&self) -> usize {
-        self.samples_per_input
+    fn get_damping_factor(&self) -> f64 {
+        self.damping_factor
     }
 }
 
 impl ICHAModel {
+    /// Clock generator defaults of ICHA, with the clock energies in meV. At
+    /// `amplitude_max` the cells keep their state.
+    const CLOCK_GENERATOR_DEFAULTS: ClockGeneratorSettings = ClockGeneratorSettings {
+        amplitude_min: 0.0000237177,
+        amplitude_max: 2.0,
+        ..ClockGeneratorSettings::DEFAULT
+    };
+
     pub fn new() -> Self {
         ICHAModel {
-            clock_states: [0.0, 0.0, 0.0, 0.0],
+            clock_states: ClockStates::default(),
             input_states: vec![],
             model_settings: ICHAModelSettings::new(),
-            clock_generator_settings: ICHAClockGeneratorSettings::new(),
-            layer_map: HashMap::new(),
-            cell_architectures_map: HashMap::new(),
-            cell_input_map: HashMap::new(),
-            index_cells_static_map: HashMap::new(),
-            index_cells_read_map: HashMap::new(),
-            index_cells_write_map: HashMap::new(),
+            clock_generator_settings: Self::CLOCK_GENERATOR_DEFAULTS,
+            cell_ids: CellIds::new(&[]),
+            cells: vec![],
+            states: CellStates::new(&[]),
+            ground_states: vec![],
+            input_cells: vec![],
+            couplings: vec![],
         }
     }
+
+    /// New dot charge probability and ground state of a dynamic cell, or
+    /// `None` for fixed cells. With `latest_states` the neighbour states
+    /// already calculated in this iteration are used, otherwise those of the
+    /// previous iteration.
+    fn update_cell(&self, cell_id: usize, latest_states: bool) -> Option<(Vec<f64>, DVector<f64>)> {
+        let internal_cell = self.cells[cell_id].as_ref()?;
+        let old_charge_probability = self.states.current(cell_id);
+        let n = old_charge_probability.len();
+
+        let clock_value = self
+            .clock_states
+            .value(internal_cell.cell.clock_phase_shift);
+
+        let mut dot_potential = DVector::<f64>::zeros(n);
+        for coupling in self.couplings[cell_id].iter() {
+            let neighbour_charge_probability = if latest_states {
+                self.states.current(coupling.cell_id)
+            } else {
+                self.states.previous(coupling.cell_id)
+            };
+            let neighbour_ro_plus = 2.0 / neighbour_charge_probability.len() as f64;
+            dot_potential += &coupling.coefficients
+                * DVector::from_column_slice(neighbour_charge_probability)
+                    .add_scalar(-neighbour_ro_plus);
+        }
+
+        // The Schur decomposition and the thermal mixture use the full
+        // Hamiltonian, which keeps their results independent of the
+        // convergence of the decomposition of each block
+        let blocks = if self.model_settings.temperature > 0.0
+            || self.model_settings.eigen_solver == ICHAEigenSolver::Schur
+        {
+            std::slice::from_ref(&internal_cell.hamiltonian)
+        } else {
+            internal_cell.exchange_blocks.as_slice()
+        };
+        let hamilton_matrices: Vec<DMatrix<f64>> = blocks
+            .iter()
+            .map(|block| block.hamilton_matrix(clock_value, &dot_potential))
+            .collect();
+
+        let mut charge_probability = old_charge_probability.to_vec();
+        let mut ground_state = self.ground_states[cell_id].clone();
+        if (clock_value - self.clock_generator_settings.amplitude_max).abs() >= 1e-3 {
+            // Probability of every block state and the ground state block
+            // and vector
+            let state = if self.model_settings.temperature > 0.0 {
+                thermal_state_probabilities(
+                    &hamilton_matrices,
+                    K_B * self.model_settings.temperature,
+                    self.model_settings.solver_convergence_tolerance,
+                    self.model_settings.solver_max_iterations,
+                )
+            } else {
+                blocks
+                    .iter()
+                    .zip(hamilton_matrices.iter())
+                    .map(|(block, hamilton_matrix)| {
+                        let block_ground_state = self.model_settings.eigen_solver.ground_state(
+                            hamilton_matrix,
+                            &block.basis.tr_mul(&ground_state),
+                            self.model_settings.solver_convergence_tolerance,
+                            self.model_settings.solver_max_iterations,
+                        )?;
+                        let energy =
+                            block_ground_state.dot(&(hamilton_matrix * &block_ground_state));
+                        Some((energy, block_ground_state))
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .and_then(|ground_states| {
+                        // Nearly degenerate blocks within the solver tolerance
+                        // keep the exchange symmetric ground state, so that
+                        // the choice does not alternate between iterations
+                        let tolerance = self.model_settings.solver_convergence_tolerance;
+                        let (block, (_, block_ground_state)) = ground_states
+                            .iter()
+                            .enumerate()
+                            .min_by(|(i, (a, _)), (j, (b, _))| {
+                                let bias =
+                                    |block: &usize| if *block == 0 { tolerance } else { 0.0 };
+                                (a - bias(i)).total_cmp(&(b - bias(j)))
+                            })?;
+                        let probabilities = ground_states
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (_, state))| {
+                                if i == block {
+                                    state.map(|value| value.powi(2))
+                                } else {
+                                    DVector::zeros(state.len())
+                                }
+                            })
+                            .collect();
+                        Some((probabilities, block, block_ground_state.clone()))
+                    })
+            };
+            if let Some((probabilities, block, block_ground_state)) = state {
+                charge_probability = blocks
+                    .iter()
+                    .zip(probabilities.iter())
+                    .map(|(block, psi)| block.occupation_matrix.tr_mul(psi))
+                    .sum::<DVector<f64>>()
+                    .data
+                    .into();
+                ground_state = &blocks[block].basis * block_ground_state;
+            }
+        }
+
+        Some((charge_probability, ground_state))
+    }
+
+    /// Applies an update to a cell and returns its residual.
+    fn store_update(
+        &mut self,
+        cell_id: usize,
+        charge_probability: &[f64],
+        ground_state: DVector<f64>,
+    ) -> f64 {
+        self.ground_states[cell_id] = ground_state;
+        let relaxation = self
+            .model_settings
+            .update_scheme
+            .relaxation_factor(self.model_settings.damping_factor);
+        self.states.relax(cell_id, charge_probability, relaxation)
+    }
 }
 
 impl SimulationModelTrait for ICHAModel {
@@ -494,126 +733,126 @@ impl SimulationModelTrait for ICHAModel {
     }
 
     fn get_model_options_list(&self) -> OptionsList {
-        vec![
-            OptionsEntry::Input {
-                unique_id: "max_iterations".into(),
-                name: "Max Iterations".into(),
-                description: "Maximum number of iterations for the simulation".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: Some(1.0),
-                    max: None,
-                    unit: None,
-                    whole_num: true,
+        [
+            vec![
+                OptionsEntry::Input {
+                    unique_id: "max_iterations".into(),
+                    name: "Max Iterations".into(),
+                    description: "Maximum number of iterations for the simulation".into(),
+                    descriptor: InputDescriptor::NumberInput {
+                        min: Some(1.0),
+                        max: None,
+                        unit: None,
+                        whole_num: true,
+                    },
                 },
-            },
-            OptionsEntry::Input {
-                unique_id: "convergence_tolerance".into(),
-                name: "Convergence Tolerance".into(),
-                description: "Tolerance value for convergence check".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: Some(0.0),
-                    max: None,
-                    unit: None,
-                    whole_num: false,
+                OptionsEntry::Input {
+                    unique_id: "convergence_tolerance".into(),
+                    name: "Convergence Tolerance".into(),
+                    description: "Tolerance value for convergence check".into(),
+                    descriptor: InputDescriptor::NumberInput {
+                        min: Some(0.0),
+                        max: None,
+                        unit: None,
+                        whole_num: false,
+                    },
                 },
-            },
-            OptionsEntry::Input {
-                unique_id: "relative_permitivity".into(),
-                name: "Relative Permittivity".into(),
-                description: "Relative permittivity value for electric field calculations".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: Some(0.0),
-                    max: None,
-                    unit: None,
-                    whole_num: false,
+            ],
+            update_scheme_options(),
+            vec![
+                OptionsEntry::Input {
+                    unique_id: "relative_permitivity".into(),
+                    name: "Relative Permittivity".into(),
+                    description: "Relative permittivity value for electric field calculations"
+                        .into(),
+                    descriptor: InputDescriptor::NumberInput {
+                        min: Some(0.0),
+                        max: None,
+                        unit: None,
+                        whole_num: false,
+                    },
                 },
-            },
-            OptionsEntry::Header {
-                label: "Schur decomposition calculation settings".into(),
-            },
-            OptionsEntry::Input {
-                unique_id: "schur_max_iterations".into(),
-                name: "Maximum iterations".into(),
-                description: "Maximum iterations for Schur decomposition".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: Some(1.0),
-                    max: None,
-                    unit: None,
-                    whole_num: true,
+                OptionsEntry::Input {
+                    unique_id: "neighborhood_radius".into(),
+                    name: "Radius of effect".into(),
+                    description: "Radius of effect for neighbouring cells, 0 includes all cells"
+                        .into(),
+                    descriptor: InputDescriptor::NumberInput {
+                        min: Some(0.0),
+                        max: None,
+                        unit: Some("nm".into()),
+                        whole_num: false,
+                    },
                 },
-            },
-            OptionsEntry::Input {
-                unique_id: "schur_convergence_tolerance".into(),
-                name: "Convergence tolerance".into(),
-                description: "Tolerance value for Schur decomposition convergence".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: Some(0.0),
-                    max: None,
-                    unit: None,
-                    whole_num: false,
+                OptionsEntry::Input {
+                    unique_id: "temperature".into(),
+                    name: "Temperature".into(),
+                    description: "Temperature of the thermal mixture of cell eigenstates, 0 uses \
+                        the ground state only. Finite temperatures always use the Schur \
+                        decomposition"
+                        .into(),
+                    descriptor: InputDescriptor::NumberInput {
+                        min: Some(0.0),
+                        max: None,
+                        unit: Some("K".into()),
+                        whole_num: false,
+                    },
                 },
-            },
-        ]
-    }
-
-    fn get_clock_generator_options_list(&self) -> OptionsList {
-        vec![
-            OptionsEntry::Input {
-                unique_id: "num_cycles".into(),
-                name: "Number of Cycles".into(),
-                description: "Number of clock cycles for simulation".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: Some(1.0),
-                    max: None,
-                    unit: None,
-                    whole_num: true,
-                },
-            },
-            OptionsEntry::Input {
-                unique_id: "amplitude_min".into(),
-                name: "Amplitude Minimum".into(),
-                description: "Minimum amplitude for clock signals".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: None,
-                    max: None,
-                    unit: None,
-                    whole_num: false,
+                OptionsEntry::Header {
+                    label: "Eigen solver settings".into(),
                 },
-            },
-            OptionsEntry::Input {
-                unique_id: "amplitude_max".into(),
-                name: "Amplitude Maximum".into(),
-                description: "Maximum amplitude for clock signals".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: None,
-                    max: None,
-                    unit: None,
-                    whole_num: false,
+                OptionsEntry::Input {
+                    unique_id: "eigen_solver".into(),
+                    name: "Eigen solver".into(),
+                    description: "Method used to find the ground state of each cell".into(),
+                    descriptor: InputDescriptor::SelectInput {
+                        options: vec![
+                            SelectOption {
+                                value: "schur".into(),
+                                label: "Schur decomposition".into(),
+                            },
+                            SelectOption {
+                                value: "symmetric".into(),
+                                label: "Symmetric eigen decomposition".into(),
+                            },
+                            SelectOption {
+                                value: "lanczos".into(),
+                                label: "Lanczos (lowest eigenpair)".into(),
+                            },
+                        ],
+                    },
                 },
-            },
-            OptionsEntry::Input {
-                unique_id: "extra_periods".into(),
-                name: "Extra clock periods".into(),
-                description: "Number of extra clock periods at the end".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: Some(0.0),
-                    max: None,
-                    unit: None,
-                    whole_num: true,
+                OptionsEntry::Input {
+                    unique_id: "solver_max_iterations".into(),
+                    name: "Maximum iterations".into(),
+                    description: "Maximum iterations of the eigen solver".into(),
+                    descriptor: InputDescriptor::NumberInput {
+                        min: Some(1.0),
+                        max: None,
+                        unit: None,
+                        whole_num: true,
+                    },
                 },
-            },
-            OptionsEntry::Input {
-                unique_id: "samples_per_input".into(),
-                name: "Samples Per Input".into(),
-                description: "Number of samples to take per input value".into(),
-                descriptor: InputDescriptor::NumberInput {
-                    min: Some(1.0),
-                    max: None,
-                    unit: None,
-                    whole_num: true,
+                OptionsEntry::Input {
+                    unique_id: "solver_convergence_tolerance".into(),
+                    name: "Convergence tolerance".into(),
+                    description: "Tolerance value for eigen solver convergence".into(),
+                    descriptor: InputDescriptor::NumberInput {
+                        min: Some(0.0),
+                        max: None,
+                        unit: None,
+                        whole_num: false,
+                    },
                 },
-            },
+            ],
         ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn get_clock_generator_options_list(&self) -> OptionsList {
+        clock_generator_options(true)
     }
 
     fn serialize_model_settings(&self) -> Result<String, String> {
@@ -644,7 +883,7 @@ impl SimulationModelTrait for ICHAModel {
         &mut self,
         settings_str: &String,
     ) -> Result<(), String> {
-        match serde_json::from_str::<ICHAClockGeneratorSettings>(settings_str) {
+        match ClockGeneratorSettings::from_json(settings_str, &Self::CLOCK_GENERATOR_DEFAULTS) {
             Ok(res) => {
                 self.clock_generator_settings = res;
                 Ok(())
@@ -658,210 +897,241 @@ impl SimulationModelTrait for ICHAModel {
         layers: Box<Vec<QCALayer>>,
         qca_architetures_map: HashMap<String, QCACellArchitecture>,
     ) {
-        self.index_cells_read_map.clear();
-        self.index_cells_write_map.clear();
-        self.layer_map.clear();
-        self.cell_architectures_map = qca_architetures_map.clone();
-
-        let mut cell_input_cnt = 0;
+        self.cell_ids = CellIds::new(&layers);
+
+        let indexed_cells = || {
+            layers.iter().enumerate().flat_map(|(i, layer)| {
+                layer
+                    .cells
+                    .iter()
+                    .enumerate()
+                    .map(move |(j, cell)| (QCACellIndex::new(i, j), layer, cell))
+            })
+        };
+        let architecture = |layer: &QCALayer| {
+            qca_architetures_map
+                .get(&layer.cell_architecture_id)
+                .unwrap()
+        };
 
-        layers.iter().enumerate().for_each(|(i, layer)| {
-            self.layer_map.insert(i, layer.clone());
-            layer.cells.iter().enumerate().for_each(|(j, c)| {
-                let mut internal = QCACellInternal::new(
+        self.cells = indexed_cells()
+            .map(|(_, layer, c)| match c.typ {
+                CellType::Normal | CellType::Output => Some(QCACellInternal::new(
                     Box::new(c.clone()),
                     layer,
-                    qca_architetures_map
-                        .get(&layer.cell_architecture_id)
-                        .unwrap(),
+                    architecture(layer),
                     self.model_settings.relative_permitivity,
-                );
-
-                match c.typ {
-                    CellType::Normal | CellType::Output => {
-                        self.index_cells_write_map
-                            .insert(QCACellIndex::new(i, j), internal);
-                    }
-                    CellType::Input => {
-                        self.index_cells_static_map
-                            .insert(QCACellIndex::new(i, j), internal.clone());
-                        self.cell_input_map
-                            .insert(QCACellIndex::new(i, j), cell_input_cnt);
-                        cell_input_cnt += 1;
-                    }
-                    CellType::Fixed => {
-                        internal.dot_charge_probability =
-                            DVector::from_vec(internal.cell.dot_probability_distribution.clone());
-                        self.index_cells_static_map
-                            .insert(QCACellIndex::new(i, j), internal.clone());
+                )),
+                CellType::Input | CellType::Fixed => None,
+            })
+            .collect();
+        self.states = CellStates::new(
+            &indexed_cells()
+                .map(|(_, layer, c)| match c.typ {
+                    CellType::Fixed => c.dot_probability_distribution.clone(),
+                    _ => {
+                        let n = architecture(layer).dot_count as usize;
+                        vec![2.0 / n as f64; n]
                     }
+                })
+                .collect::<Vec<_>>(),
+        );
+        self.ground_states = self
+            .cells
+            .iter()
+            .map(|internal| match internal {
+                Some(internal) => {
+                    DVector::<f64>::from_element(internal.hamiltonian.basis.nrows(), 1.0)
                 }
+                None => DVector::<f64>::zeros(0),
             })
-        });
-
-        self.index_cells_read_map = self.index_cells_write_map.clone();
+            .collect();
+        self.input_cells = indexed_cells()
+            .enumerate()
+            .filter(|(_, (_, _, c))| c.typ == CellType::Input)
+            .enumerate()
+            .map(|(input_index, (cell_id, _))| (cell_id, input_index))
+            .collect();
+
+        let radius = self.model_settings.neighborhood_radius;
+        let neighborhood_index =
+            NeighborhoodIndex::new(&layers, if radius > 0.0 { Some(radius) } else { None });
+        let vq = calculate_vq(self.model_settings.relative_permitivity);
+        let dot_positions = |ind: &QCACellIndex| {
+            let layer = &layers[ind.layer];
+            let cell_architecture = architecture(layer);
+            let cell = Box::new(layer.cells[ind.cell].clone());
+            (0..cell_architecture.dot_count as usize)
+                .map(|i| QCACellInternal::get_dot_position(i, &cell, layer, cell_architecture))
+                .collect::<Vec<_>>()
+        };
+        self.couplings = indexed_cells()
+            .map(|(ind, _, c)| {
+                if !matches!(c.typ, CellType::Normal | CellType::Output) {
+                    return vec![];
+                }
+                let cell_dots = dot_positions(&ind);
+                neighborhood_index
+                    .neighbors(&ind, c.position)
+                    .into_iter()
+                    .map(|neighbour_ind| {
+                        let neighbour_dots = dot_positions(&neighbour_ind);
+                        ICHACoupling {
+                            coefficients: DMatrix::from_fn(
+                                cell_dots.len(),
+                                neighbour_dots.len(),
+                                |i, j| vq / distance(&cell_dots[i], &neighbour_dots[j]),
+                            ),
+                            cell_id: self.cell_ids.id(&neighbour_ind),
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
     }
 
-    fn pre_calculate(&mut self, clock_states: &[f64; 4], input_states: &Vec<f64>) {
+    fn pre_calculate(&mut self, clock_states: &ClockStates, input_states: &Vec<f64>) {
         self.clock_states = clock_states.clone();
         self.input_states = input_states.clone();
-        mem::swap(
-            &mut self.index_cells_read_map,
-            &mut self.index_cells_write_map,
-        );
-        self.index_cells_write_map = self.index_cells_read_map.clone();
-
-        self.index_cells_static_map
-            .iter_mut()
-            .for_each(|(ind, cell)| {
-                if let Some(input_i) = self.cell_input_map.get(&ind) {
-                    let layer = self.layer_map.get(&ind.layer).unwrap();
-                    let cell_architecture = self
-                        .cell_architectures_map
-                        .get(&layer.cell_architecture_id)
-                        .unwrap();
-                    let n = cell_architecture.dot_count as usize;
-                    let cell_state_num = n / 4;
-
-                    let input = self.input_states
-                        [(cell_state_num * input_i)..(cell_state_num * input_i + cell_state_num)]
-                        .to_vec();
-                    let input_distribution =
-                        polarization_to_dot_probability_distribution(input.as_slice());
-                    cell.dot_charge_probability = DVector::from_vec(input_distribution);
-                }
-            });
+        self.states.advance();
+
+        for &(cell_id, input_index) in self.input_cells.iter() {
+            let cell_state_num = self.states.current(cell_id).len() / 4;
+            let input = &input_states
+                [(cell_state_num * input_index)..(cell_state_num * input_index + cell_state_num)];
+            self.states.set_fixed(
+                cell_id,
+                &polarization_to_dot_probability_distribution(input),
+            );
+        }
     }
 
-    fn calculate(&mut self, cell_ind: QCACellIndex) -> bool {
-        let cell_option = self.index_cells_write_map.get(&cell_ind);
-
-        if cell_option.is_none() {
-            return true;
+    fn calculate(&mut self, cell_ind: QCACellIndex) -> f64 {
+        let cell_id = self.cell_ids.id(&cell_ind);
+        let latest_states = self.model_settings.update_scheme.uses_latest_states();
+        match self.update_cell(cell_id, latest_states) {
+            Some((charge_probability, ground_state)) => {
+                self.store_update(cell_id, &charge_probability, ground_state)
+            }
+            None => 0.0,
         }
+    }
 
-        let mut internal_cell = cell_option.unwrap().clone();
-        let old_charge_probability = internal_cell.dot_charge_probability.clone();
-
-        let layer = self.layer_map.get(&cell_ind.layer).unwrap();
-        let cell_architecture = self
-            .cell_architectures_map
-            .get(&layer.cell_architecture_id)
-            .unwrap();
-        let n = cell_architecture.dot_count as usize;
-        let ro_plus = 2.0 / n as f64;
-
-        let clock_index = (internal_cell.cell.clock_phase_shift.rem_euclid(360.0) / 90.0) as usize;
-        let clock_value = self.clock_states[clock_index];
-
-        internal_cell.hamilton_matrix = &internal_cell.static_hamilton_matrix
-            + &internal_cell.dynamic_hamilton_matrix * clock_value;
+    fn calculate_all(&mut self, cell_indices: &[QCACellIndex], workers: &WorkerPool) -> Vec<f64> {
+        if self.model_settings.update_scheme.uses_latest_states() {
+            return cell_indices
+                .iter()
+                .map(|cell_ind| self.calculate(cell_ind.clone()))
+                .collect();
+        }
 
-        internal_cell.dot_potential = DVector::zeros(n);
-        for (ind, c) in self
-            .index_cells_static_map
+        let cell_ids: Vec<usize> = cell_indices
             .iter()
-            .chain(self.index_cells_write_map.iter())
-        {
-            if *ind != cell_ind {
-                for i in 0..n {
-                    for j in 0..n {
-                        let dot_pos_i = QCACellInternal::get_dot_position(
-                            i,
-                            &internal_cell.cell,
-                            layer,
-                            cell_architecture,
-                        );
-                        let dot_pos_j =
-                            QCACellInternal::get_dot_position(j, &c.cell, layer, cell_architecture);
-
-                        let distance = distance(&dot_pos_i, &dot_pos_j);
-
-                        internal_cell.dot_potential[i] +=
-                            (calculate_vq(self.model_settings.relative_permitivity)
-                                * (c.dot_charge_probability[j] - ro_plus))
-                                / distance;
-                    }
+            .map(|cell_ind| self.cell_ids.id(cell_ind))
+            .collect();
+        let updates = workers.map(&cell_ids, |cell_id| self.update_cell(*cell_id, false));
+
+        cell_ids
+            .into_iter()
+            .zip(updates)
+            .map(|(cell_id, update)| match update {
+                Some((charge_probability, ground_state)) => {
+                    self.store_update(cell_id, &charge_probability, ground_state)
                 }
-            }
-        }
+                None => 0.0,
+            })
+            .collect()
+    }
 
-        for i in 0..n * n {
-            internal_cell.hamilton_matrix[(i, i)] = QCACellInternal::hamilton_term_1(
-                n,
-                0.0,
-                internal_cell.dot_potential.as_view(),
-                internal_cell.basis_matrix.row(i).transpose().as_view(),
-                internal_cell.basis_matrix.row(i).transpose().as_view(),
-            ) + &internal_cell.static_hamilton_matrix
-                [(i, i)];
-        }
+    fn get_states(&self, cell_ind: &QCACellIndex) -> Vec<f64> {
+        self.states.current(self.cell_ids.id(cell_ind)).to_vec()
+    }
+}
 
-        if (clock_value - self.clock_generator_settings.amplitude_max).abs() >= 1e-3 {
-            if let Some(decomposition) = Schur::try_new(
-                internal_cell.hamilton_matrix.clone(),
-                self.model_settings.schur_convergence_tolerance,
-                self.model_settings.schur_max_iterations,
-            ) {
-                if let Some(eigenvalues) = decomposition.eigenvalues() {
-                    let sorted_eigenvalue = eigenvalues
-                        .iter()
-                        .enumerate()
-                        .min_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                        .unwrap();
-
-                    let psi = decomposition
-                        .unpack()
-                        .0
-                        .column(sorted_eigenvalue.0)
-                        .map(|value| value.powf(2.0));
-
-                    internal_cell.dot_charge_probability = DVector::from_vec(
-                        (0..n)
-                            .map(|i| {
-                                let mut charge_probability = 0.0;
-                                for j in 0..n * n {
-                                    for spin in 0..=1 {
-                                        charge_probability += QCACellInternal::count_operator(
-                                            n,
-                                            i,
-                                            spin,
-                                            internal_cell.basis_matrix.row(j).transpose().as_view(),
-                                        ) * psi[j];
-                                    }
-                                }
-                                charge_probability
-                            })
-                            .collect(),
-                    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::cell::dot_probability_distribution_to_polarization;
+    use crate::simulation::test_designs::{tri_state_wire, wire};
+
+    fn relax(model: &mut ICHAModel, layers: &[QCALayer], input: &[f64], workers: &WorkerPool) {
+        let cell_indices: Vec<QCACellIndex> = (0..layers[0].cells.len())
+            .map(|c| QCACellIndex::new(0, c))
+            .collect();
+        for clock in [1.0, 0.2] {
+            let clock_states = ClockStates::zones(vec![clock; 4]);
+            for _ in 0..model.model_settings.max_iterations {
+                model.pre_calculate(&clock_states, &input.to_vec());
+                let residuals = model.calculate_all(&cell_indices, workers);
+                if residuals.into_iter().fold(0.0, f64::max)
+                    <= model.model_settings.convergence_tolerance
+                {
+                    break;
                 }
             }
         }
+    }
 
-        let mut stable: bool = true;
-        for i in 0..n as usize {
-            if (internal_cell.dot_charge_probability[i] - old_charge_probability[i]).abs()
-                > self.model_settings.convergence_tolerance
-            {
-                stable = false;
-            }
-        }
-
-        self.index_cells_write_map.insert(cell_ind, internal_cell);
+    #[test]
+    fn test_jacobi_threads_match_sequential() {
+        // Jacobi updates of a wire oscillate at low clock values, a few
+        // iterations suffice to compare the threaded updates
+        let (layers, architectures) = tri_state_wire(4);
+        let states: Vec<Vec<Vec<f64>>> = [1, 3]
+            .iter()
+            .map(|num_threads| {
+                let mut model = ICHAModel::new();
+                model
+                    .deserialize_model_settings(
+                        &r#"{"update_scheme": "jacobi", "max_iterations": 10}"#.to_string(),
+                    )
+                    .unwrap();
+                model.initiate(Box::new(layers.clone()), architectures.clone());
+                relax(
+                    &mut model,
+                    &layers,
+                    &[0.0, -0.3],
+                    &WorkerPool::new(*num_threads),
+                );
+                (0..layers[0].cells.len())
+                    .map(|c| model.get_states(&QCACellIndex::new(0, c)))
+                    .collect()
+            })
+            .collect();
 
-        stable
+        assert_eq!(states[0], states[1]);
     }
 
-    fn get_states(&self, cell_ind: &QCACellIndex) -> Vec<f64> {
-        if let Some(c) = self.index_cells_write_map.get(cell_ind) {
-            return c.dot_charge_probability.data.as_vec().to_vec();
-        }
-        if let Some(c) = self.index_cells_read_map.get(cell_ind) {
-            return c.dot_charge_probability.data.as_vec().to_vec();
-        }
-        if let Some(c) = self.index_cells_static_map.get(cell_ind) {
-            return c.dot_charge_probability.data.as_vec().to_vec();
+    #[test]
+    fn test_polarizations_match_per_iteration_couplings() {
+        // Polarizations of the model calculating the couplings of every
+        // iteration from the dot distances, before they were precomputed
+        let cases = [
+            (wire(4), vec![0.3], vec![vec![-1.0], vec![1.0], vec![-1.0]]),
+            (
+                tri_state_wire(3),
+                vec![0.0, -0.3],
+                vec![vec![0.0, 0.849_012_432], vec![0.0, -0.852_796_367]],
+            ),
+            (
+                tri_state_wire(3),
+                vec![1.0, 0.0],
+                vec![vec![0.894_359_943, 0.0], vec![0.764_785_142, 0.0]],
+            ),
+        ];
+        for ((layers, architectures), input, expected) in cases {
+            let mut model = ICHAModel::new();
+            model.initiate(Box::new(layers.clone()), architectures);
+            relax(&mut model, &layers, &input, &WorkerPool::default());
+
+            for (c, expected) in expected.iter().enumerate() {
+                let polarization = dot_probability_distribution_to_polarization(
+                    &model.get_states(&QCACellIndex::new(0, c + 1)),
+                );
+                for (p, e) in polarization.iter().zip(expected) {
+                    assert!((p - e).abs() < 1e-4, "cell {}: {} != {}", c + 1, p, e);
+                }
+            }
         }
-        panic!("Cell not found");
     }
 }
@@ -1,5 +1,55 @@
 use crate::objects::generator::{Generator, GeneratorConfig};
+use crate::simulation::stimulus::{StimulusInputConfig, StimulusInputGenerator};
 use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+/// Transition of the inputs from one input combination to the next.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InputTransition {
+    /// Instant switch at the start of the input period
+    Step,
+    /// Linear ramp over the ramp fraction of the input period
+    Linear,
+    /// Raised-cosine ramp over the ramp fraction of the input period
+    RaisedCosine,
+}
+
+/// Shape of the input signals
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct InputWaveform {
+    pub transition: InputTransition,
+    /// Fraction of the input period taken by a transition
+    pub ramp_fraction: f64,
+    /// Polarization magnitude of a driven input
+    pub magnitude: f64,
+}
+
+impl Default for InputWaveform {
+    fn default() -> Self {
+        InputWaveform {
+            transition: InputTransition::Step,
+            ramp_fraction: 0.25,
+            magnitude: 1.0,
+        }
+    }
+}
+
+impl InputWaveform {
+    /// Weight of the new input values at `progress` (fraction of the input
+    /// period), from 0 at the start of a transition to 1 once it is done.
+    pub fn transition_weight(&self, progress: f64) -> f64 {
+        if self.ramp_fraction <= 0.0 {
+            return 1.0;
+        }
+        let ramp_progress = (progress / self.ramp_fraction).min(1.0);
+        match self.transition {
+            InputTransition::Step => 1.0,
+            InputTransition::Linear => ramp_progress,
+            InputTransition::RaisedCosine => (1.0 - (PI * ramp_progress).cos()) / 2.0,
+        }
+    }
+}
 
 /// Configuration for cell input generator
 #[derive(Clone, Serialize, Deserialize)]
@@ -12,6 +62,11 @@ pub struct CellInputConfig {
     pub num_polarization: usize,
     /// Number of extra clock periods to generate
     pub extra_clock_periods: usize,
+    /// Number of samples before the first input change, with unpolarized
+    /// inputs
+    pub offset: usize,
+    /// Shape of the input signals
+    pub waveform: InputWaveform,
 }
 
 impl GeneratorConfig for CellInputConfig {}
@@ -22,7 +77,6 @@ impl GeneratorConfig for CellInputConfig {}
 pub struct CellInputGenerator {
     config: CellInputConfig,
     num_samples: usize,
-    extra_samples: usize,
 }
 
 impl Generator for CellInputGenerator {
@@ -32,11 +86,11 @@ impl Generator for CellInputGenerator {
     fn new(config: Self::Config) -> Self {
         let input_combinations = (config.num_polarization + 1).pow(config.num_inputs as u32);
         let extra_samples = config.extra_clock_periods * config.num_samples_per_combination;
-        let num_samples = config.num_samples_per_combination * input_combinations + extra_samples;
+        let num_samples =
+            config.offset + config.num_samples_per_combination * input_combinations + extra_samples;
         Self {
             config,
             num_samples,
-            extra_samples,
         }
     }
 
@@ -45,16 +99,52 @@ impl Generator for CellInputGenerator {
             return None;
         }
 
-        if sample >= self.num_samples - self.extra_samples {
+        if sample < self.config.offset {
             return Some(vec![
                 0.0;
                 self.config.num_inputs * self.config.num_polarization
             ]);
         }
+        let sample = sample - self.config.offset;
 
-        // Calculate which combination we're in
+        // Calculate which combination we're in, the extra samples follow the
+        // last combination with unpolarized inputs
         let samples_per_combination = self.config.num_samples_per_combination;
         let combination_index = sample / samples_per_combination;
+        let progress = (sample % samples_per_combination) as f64 / samples_per_combination as f64;
+
+        let weight = self.config.waveform.transition_weight(progress);
+        let output = self.combination_values(combination_index);
+        if weight >= 1.0 {
+            return Some(output);
+        }
+
+        let previous = match combination_index {
+            0 => vec![0.0; output.len()],
+            index => self.combination_values(index - 1),
+        };
+        Some(
+            previous
+                .into_iter()
+                .zip(output)
+                .map(|(previous, value)| previous + (value - previous) * weight)
+                .collect(),
+        )
+    }
+
+    fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+}
+
+impl CellInputGenerator {
+    /// Input values of a combination once its transition is done
+    fn combination_values(&self, combination_index: usize) -> Vec<f64> {
+        let num_combinations =
+            (self.config.num_polarization + 1).pow(self.config.num_inputs as u32);
+        if combination_index >= num_combinations {
+            return vec![0.0; self.config.num_inputs * self.config.num_polarization];
+        }
 
         // Generate the combination pattern
         let combination = self.get_combination(combination_index);
@@ -65,19 +155,13 @@ impl Generator for CellInputGenerator {
         for input_idx in 0..self.config.num_inputs {
             for pol_idx in 0..self.config.num_polarization {
                 let value = self.generate_signal_value(input_idx, pol_idx, &combination);
-                output.push(value);
+                output.push(value * self.config.waveform.magnitude);
             }
         }
 
-        Some(output)
+        output
     }
 
-    fn num_samples(&self) -> usize {
-        self.num_samples
-    }
-}
-
-impl CellInputGenerator {
     /// Get the combination pattern for a given combination index
     /// Each input can have values from 0 to (num_polarization)
     fn get_combination(&self, combination_index: usize) -> Vec<usize> {
@@ -116,3 +200,94 @@ impl CellInputGenerator {
         }
     }
 }
+
+/// Configuration of the input generator of a run
+#[derive(Clone, Serialize, Deserialize)]
+pub enum InputConfig {
+    /// Enumerates all input combinations
+    Combinations(CellInputConfig),
+    /// Follows a stimulus cycle by cycle
+    Stimulus(StimulusInputConfig),
+}
+
+impl GeneratorConfig for InputConfig {}
+
+/// Generator for cell input values selected by the input configuration
+pub enum InputGenerator {
+    Combinations(CellInputGenerator),
+    Stimulus(StimulusInputGenerator),
+}
+
+impl Generator for InputGenerator {
+    type Config = InputConfig;
+    type Output = Vec<f64>;
+
+    fn new(config: Self::Config) -> Self {
+        match config {
+            InputConfig::Combinations(config) => {
+                InputGenerator::Combinations(CellInputGenerator::new(config))
+            }
+            InputConfig::Stimulus(config) => {
+                InputGenerator::Stimulus(StimulusInputGenerator::new(config))
+            }
+        }
+    }
+
+    fn generate(&self, sample: usize) -> Option<Self::Output> {
+        match self {
+            InputGenerator::Combinations(generator) => generator.generate(sample),
+            InputGenerator::Stimulus(generator) => generator.generate(sample),
+        }
+    }
+
+    fn num_samples(&self) -> usize {
+        match self {
+            InputGenerator::Combinations(generator) => generator.num_samples(),
+            InputGenerator::Stimulus(generator) => generator.num_samples(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_ramps() {
+        let config = CellInputConfig {
+            num_samples_per_combination: 4,
+            num_inputs: 1,
+            num_polarization: 1,
+            extra_clock_periods: 1,
+            offset: 0,
+            waveform: InputWaveform::default(),
+        };
+        let step: Vec<Vec<f64>> = CellInputGenerator::new(config.clone()).iter().collect();
+        assert_eq!(
+            step.concat(),
+            vec![1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0, 0.0, 0.0, 0.0, 0.0]
+        );
+
+        let linear = CellInputGenerator::new(CellInputConfig {
+            waveform: InputWaveform {
+                transition: InputTransition::Linear,
+                ramp_fraction: 0.5,
+                magnitude: 0.5,
+            },
+            ..config.clone()
+        });
+        assert_eq!(
+            linear.iter().collect::<Vec<_>>().concat(),
+            vec![0.0, 0.25, 0.5, 0.5, 0.5, 0.0, -0.5, -0.5, -0.5, -0.25, 0.0, 0.0]
+        );
+
+        let offset = CellInputGenerator::new(CellInputConfig {
+            offset: 2,
+            ..config
+        });
+        assert_eq!(
+            offset.iter().collect::<Vec<_>>().concat(),
+            [vec![0.0, 0.0], step.concat()].concat()
+        );
+    }
+}
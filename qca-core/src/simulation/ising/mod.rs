@@ -0,0 +1,639 @@
+use super::{CellType, QCACellArchitecture, SimulationModelTrait};
+use crate::objects::cell::{
+    dot_probability_distribution_to_polarization, polarization_to_dot_probability_distribution,
+    QCACell, QCACellIndex,
+};
+use crate::objects::layer::QCALayer;
+use crate::simulation::bistable::{kink_energy_neighborhood, BistableNeighbor};
+use crate::simulation::cell_state::{CellIds, CellStates};
+use crate::simulation::clock_generator::ClockStates;
+use crate::simulation::model::{
+    clock_generator_options, ClockGeneratorSettings, ClockGeneratorSettingsTrait,
+    SimulationModelSettingsTrait, WorkerPool,
+};
+use crate::simulation::settings::{InputDescriptor, OptionsEntry, OptionsList};
+use log::{debug, warn};
+use nalgebra::{DMatrix, DVector};
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use std::collections::{HashMap, VecDeque};
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IsingModelSettings {
+    #[serde_inline_default(65.0)]
+    neighborhood_radius: f64,
+
+    #[serde_inline_default(12.9)]
+    relative_permitivity: f64,
+
+    #[serde_inline_default(1e-6)]
+    energy_tolerance: f64,
+
+    #[serde_inline_default(10_000_000)]
+    max_search_nodes: usize,
+}
+
+impl IsingModelSettings {
+    pub fn new() -> Self {
+        serde_json::from_str::<IsingModelSettings>("{}").unwrap()
+    }
+}
+
+impl Default for IsingModelSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The ground state is solved directly.
+impl SimulationModelSettingsTrait for IsingModelSettings {
+    fn relaxes_samples(&self) -> bool {
+        false
+    }
+}
+
+/// Polarization of spin state `state` of a cell with `axes` polarization
+/// axes. A cell has one fully polarized state per axis and sign, the same
+/// states the bistable cell response switches between.
+fn spin_polarization(state: usize, axes: usize) -> DVector<f64> {
+    let mut polarization = DVector::<f64>::zeros(axes);
+    polarization[state % axes] = if state < axes { 1.0 } else { -1.0 };
+    polarization
+}
+
+/// Ising problem over the dynamic cells of a design, with the energy (in meV)
+/// `E = -Σ_k s_k·h_k - Σ_{j<k} s_kᵀ J_kj s_j` for the spins `s_k`.
+struct IsingProblem {
+    //Number of polarization axes of every spin
+    axes: Vec<usize>,
+    //External field `h_k` of every spin
+    fields: Vec<DVector<f64>>,
+    //Couplings `J_kj` of every spin to the spins before it
+    couplings: Vec<Vec<(usize, DMatrix<f64>)>>,
+}
+
+/// Lowest energy states of an `IsingProblem`.
+struct GroundState {
+    energy: f64,
+    //Polarization of every spin, averaged over all degenerate ground states
+    polarizations: Vec<DVector<f64>>,
+    //Number of states within the energy tolerance of the ground state
+    degeneracy: usize,
+    //Whether the search completed within the node limit
+    exact: bool,
+}
+
+/// Depth-first branch-and-bound search for the ground states.
+struct GroundStateSearch<'a> {
+    problem: &'a IsingProblem,
+    //Couplings `J_kj` of every spin `j` to the spins `k` after it
+    forward_couplings: Vec<Vec<(usize, &'a DMatrix<f64>)>>,
+    //Lower bound of the coupling energy among the spins from an index on
+    pair_bounds: Vec<f64>,
+    tolerance: f64,
+    max_nodes: usize,
+    nodes: usize,
+    assignment: Vec<usize>,
+    //Field on every spin from its external field and the assigned spins
+    effective_fields: Vec<DVector<f64>>,
+    best_energy: f64,
+    polarization_sums: Vec<DVector<f64>>,
+    degeneracy: usize,
+}
+
+impl IsingProblem {
+    /// Ground states within `tolerance` (in meV) of the lowest energy,
+    /// visiting at most `max_nodes` nodes of the search tree.
+    fn ground_state(&self, tolerance: f64, max_nodes: usize) -> GroundState {
+        let n = self.axes.len();
+        let mut forward_couplings = vec![vec![]; n];
+        for (k, couplings) in self.couplings.iter().enumerate() {
+            for (j, coupling) in couplings {
+                forward_couplings[*j].push((k, coupling));
+            }
+        }
+        // Every pair coupling is at least `-max |J_kj|` for aligned spins
+        let mut pair_bounds = vec![0.0; n + 1];
+        for start in (0..n).rev() {
+            let new_pairs: f64 = forward_couplings[start]
+                .iter()
+                .map(|(_, coupling)| -coupling.amax())
+                .sum();
+            pair_bounds[start] = pair_bounds[start + 1] + new_pairs;
+        }
+
+        let mut search = GroundStateSearch {
+            problem: self,
+            forward_couplings,
+            pair_bounds,
+            tolerance,
+            max_nodes,
+            nodes: 0,
+            assignment: vec![0; n],
+            effective_fields: self.fields.clone(),
+            best_energy: f64::INFINITY,
+            polarization_sums: self.axes.iter().map(|a| DVector::zeros(*a)).collect(),
+            degeneracy: 0,
+        };
+        search.search(0, 0.0);
+
+        let degeneracy = search.degeneracy.max(1);
+        GroundState {
+            energy: search.best_energy,
+            polarizations: search
+                .polarization_sums
+                .into_iter()
+                .map(|sum| sum / degeneracy as f64)
+                .collect(),
+            degeneracy: search.degeneracy,
+            exact: search.nodes <= max_nodes,
+        }
+    }
+}
+
+impl GroundStateSearch<'_> {
+    /// Lower bound of the energy added by the spins from `start` on.
+    fn remaining_bound(&self, start: usize) -> f64 {
+        let field_bound: f64 = self.effective_fields[start..]
+            .iter()
+            .map(|field| -field.amax())
+            .sum();
+        field_bound + self.pair_bounds[start]
+    }
+
+    fn search(&mut self, index: usize, energy: f64) {
+        self.nodes += 1;
+        if self.nodes > self.max_nodes {
+            return;
+        }
+
+        if index == self.assignment.len() {
+            if energy > self.best_energy + self.tolerance {
+                return;
+            }
+            if energy < self.best_energy - self.tolerance {
+                self.polarization_sums
+                    .iter_mut()
+                    .for_each(|sum| sum.fill(0.0));
+                self.degeneracy = 0;
+            }
+            self.best_energy = self.best_energy.min(energy);
+            for (k, state) in self.assignment.iter().enumerate() {
+                self.polarization_sums[k] += spin_polarization(*state, self.problem.axes[k]);
+            }
+            self.degeneracy += 1;
+            return;
+        }
+
+        if energy + self.remaining_bound(index) > self.best_energy + self.tolerance {
+            return;
+        }
+
+        // Try the locally favourable states first to find a good bound early
+        let axes = self.problem.axes[index];
+        let mut states: Vec<(usize, f64)> = (0..2 * axes)
+            .map(|state| {
+                let polarization = spin_polarization(state, axes);
+                (state, -polarization.dot(&self.effective_fields[index]))
+            })
+            .collect();
+        states.sort_by(|a, b| a.1.total_cmp(&b.1));
+
+        for (state, cost) in states {
+            let polarization = spin_polarization(state, axes);
+            self.assignment[index] = state;
+            for (k, coupling) in self.forward_couplings[index].iter() {
+                self.effective_fields[*k] += *coupling * &polarization;
+            }
+            self.search(index + 1, energy + cost);
+            for (k, coupling) in self.forward_couplings[index].iter() {
+                self.effective_fields[*k] -= *coupling * &polarization;
+            }
+        }
+    }
+}
+
+/// Reference model giving the exact ground state of the design for every
+/// input vector. The bistable kink energies define an Ising Hamiltonian over
+/// the fully polarized states of the dynamic cells, which is minimized by a
+/// branch-and-bound search. The clock is ignored: the result is the state an
+/// adiabatically clocked design should settle in, so relaxation models that
+/// end up elsewhere got stuck in a metastable state. Cells that differ
+/// between degenerate ground states are reported with their average
+/// polarization. Only feasible for designs of a few dozen cells.
+pub struct IsingModel {
+    input_states: Vec<f64>,
+    //Input states the current ground state was solved for
+    solved_input_states: Option<Vec<f64>>,
+    model_settings: IsingModelSettings,
+    clock_generator_settings: ClockGeneratorSettings,
+    cell_ids: CellIds,
+    cells: Vec<QCACell>,
+    //Polarization of every cell
+    polarizations: CellStates,
+    //Cell id and input index of every input cell
+    input_cells: Vec<(usize, usize)>,
+    neighborhood: Vec<Vec<BistableNeighbor>>,
+    //Dynamic cells in the order they are assigned by the search
+    search_order: Vec<usize>,
+}
+
+impl IsingModel {
+    pub fn new() -> Self {
+        IsingModel {
+            input_states: vec![],
+            solved_input_states: None,
+            model_settings: IsingModelSettings::new(),
+            clock_generator_settings: ClockGeneratorSettings::default(),
+            cell_ids: CellIds::new(&[]),
+            cells: vec![],
+            polarizations: CellStates::new(&[]),
+            input_cells: vec![],
+            neighborhood: vec![],
+            search_order: vec![],
+        }
+    }
+
+    fn is_dynamic(&self, cell_id: usize) -> bool {
+        matches!(self.cells[cell_id].typ, CellType::Normal | CellType::Output)
+    }
+
+    /// Orders the dynamic cells breadth first from the cells driven by input
+    /// and fixed cells, so that the search assigns well-determined cells
+    /// first and prunes early.
+    fn determine_search_order(&self) -> Vec<usize> {
+        let dynamic: Vec<usize> = (0..self.cells.len())
+            .filter(|cell_id| self.is_dynamic(*cell_id))
+            .collect();
+        let (driven, undriven): (Vec<usize>, Vec<usize>) = dynamic.iter().partition(|cell_id| {
+            self.neighborhood[**cell_id]
+                .iter()
+                .any(|neighbour| !self.is_dynamic(neighbour.cell_id))
+        });
+
+        let mut visited = vec![false; self.cells.len()];
+        let mut order = Vec::with_capacity(dynamic.len());
+        for start in driven.into_iter().chain(undriven) {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut queue = VecDeque::from([start]);
+            while let Some(cell_id) = queue.pop_front() {
+                order.push(cell_id);
+                for neighbour in &self.neighborhood[cell_id] {
+                    if self.is_dynamic(neighbour.cell_id) && !visited[neighbour.cell_id] {
+                        visited[neighbour.cell_id] = true;
+                        queue.push_back(neighbour.cell_id);
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    /// Ising problem of the dynamic cells for the current input and fixed
+    /// cell polarizations. The electrostatic energy of two cells is
+    /// `-½ P_iᵀ E_k P_j`, as in the bistable cell response.
+    fn build_problem(&self) -> IsingProblem {
+        let mut positions = vec![None; self.cells.len()];
+        for (position, cell_id) in self.search_order.iter().enumerate() {
+            positions[*cell_id] = Some(position);
+        }
+
+        let axes: Vec<usize> = self
+            .search_order
+            .iter()
+            .map(|cell_id| self.polarizations.current(*cell_id).len())
+            .collect();
+        let mut fields: Vec<DVector<f64>> = axes.iter().map(|a| DVector::zeros(*a)).collect();
+        let mut couplings: Vec<HashMap<usize, DMatrix<f64>>> =
+            vec![HashMap::new(); self.search_order.len()];
+
+        for (position, cell_id) in self.search_order.iter().enumerate() {
+            for neighbour in &self.neighborhood[*cell_id] {
+                match positions[neighbour.cell_id] {
+                    None => {
+                        let polarization = DVector::from_column_slice(
+                            self.polarizations.current(neighbour.cell_id),
+                        );
+                        fields[position] += &neighbour.kink_energy * polarization / 2.0;
+                    }
+                    // Both cells list each other, so each direction adds half
+                    // of the pair energy
+                    Some(other) => {
+                        let (later, earlier, coupling) = if other < position {
+                            (position, other, &neighbour.kink_energy / 4.0)
+                        } else {
+                            (other, position, neighbour.kink_energy.transpose() / 4.0)
+                        };
+                        *couplings[later]
+                            .entry(earlier)
+                            .or_insert_with(|| DMatrix::zeros(axes[later], axes[earlier])) +=
+                            coupling;
+                    }
+                }
+            }
+        }
+
+        IsingProblem {
+            axes,
+            fields,
+            couplings: couplings
+                .into_iter()
+                .map(|couplings| couplings.into_iter().collect())
+                .collect(),
+        }
+    }
+}
+
+impl Default for IsingModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulationModelTrait for IsingModel {
+    fn get_name(&self) -> String {
+        "Ising Ground State".into()
+    }
+
+    fn get_unique_id(&self) -> String {
+        "ising".into()
+    }
+
+    fn get_model_settings(&self) -> Box<dyn SimulationModelSettingsTrait> {
+        Box::new(self.model_settings.clone()) as Box<dyn SimulationModelSettingsTrait>
+    }
+
+    fn get_clock_generator_settings(&self) -> Box<dyn ClockGeneratorSettingsTrait> {
+        Box::new(self.clock_generator_settings.clone()) as Box<dyn ClockGeneratorSettingsTrait>
+    }
+
+    fn get_model_options_list(&self) -> OptionsList {
+        vec![
+            OptionsEntry::Input {
+                unique_id: "neighborhood_radius".into(),
+                name: "Radius of effect".into(),
+                description: "Radius of effect for neighbouring cells".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("nm".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "relative_permitivity".into(),
+                name: "Relative permittivity".into(),
+                description: "Relative permittivity of the medium".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: None,
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "energy_tolerance".into(),
+                name: "Energy tolerance".into(),
+                description:
+                    "States this close to the lowest energy count as degenerate ground states"
+                        .into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("meV".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "max_search_nodes".into(),
+                name: "Maximum search nodes".into(),
+                description:
+                    "Number of search tree nodes after which the best state found so far is used"
+                        .into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(1.0),
+                    max: None,
+                    unit: None,
+                    whole_num: true,
+                },
+            },
+        ]
+    }
+
+    fn get_clock_generator_options_list(&self) -> OptionsList {
+        clock_generator_options(false)
+    }
+
+    fn serialize_model_settings(&self) -> Result<String, String> {
+        serde_json::to_string(&self.model_settings).map_err(|err| err.to_string())
+    }
+
+    fn deserialize_model_settings(&mut self, settings_str: &String) -> Result<(), String> {
+        self.model_settings = serde_json::from_str::<IsingModelSettings>(settings_str)
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn serialize_clock_generator_settings(&self) -> Result<String, String> {
+        serde_json::to_string(&self.clock_generator_settings).map_err(|err| err.to_string())
+    }
+
+    fn deserialize_clock_generator_settings(
+        &mut self,
+        settings_str: &String,
+    ) -> Result<(), String> {
+        self.clock_generator_settings =
+            ClockGeneratorSettings::from_json(settings_str, &ClockGeneratorSettings::default())?;
+        Ok(())
+    }
+
+    fn initiate(
+        &mut self,
+        layers: Box<Vec<QCALayer>>,
+        qca_architetures_map: HashMap<String, QCACellArchitecture>,
+    ) {
+        self.solved_input_states = None;
+        self.cell_ids = CellIds::new(&layers);
+        self.cells = layers
+            .iter()
+            .flat_map(|layer| layer.cells.iter().cloned())
+            .collect();
+        self.polarizations = CellStates::new(
+            &self
+                .cells
+                .iter()
+                .map(|cell| {
+                    dot_probability_distribution_to_polarization(&cell.dot_probability_distribution)
+                })
+                .collect::<Vec<_>>(),
+        );
+        self.input_cells = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.typ == CellType::Input)
+            .enumerate()
+            .map(|(input_index, (cell_id, _))| (cell_id, input_index))
+            .collect();
+
+        self.neighborhood = kink_energy_neighborhood(
+            &layers,
+            &qca_architetures_map,
+            &self.cell_ids,
+            Some(self.model_settings.neighborhood_radius),
+            self.model_settings.relative_permitivity,
+        );
+        self.search_order = self.determine_search_order();
+        debug!("Ising ground state over {} cells", self.search_order.len());
+    }
+
+    fn pre_calculate(&mut self, _clock_states: &ClockStates, input_states: &Vec<f64>) {
+        self.input_states = input_states.clone();
+        for &(cell_id, input_index) in self.input_cells.iter() {
+            let polarization_count = self.polarizations.current(cell_id).len();
+            self.polarizations.set_fixed(
+                cell_id,
+                &input_states[(polarization_count * input_index)
+                    ..(polarization_count * input_index + polarization_count)],
+            );
+        }
+    }
+
+    fn calculate(&mut self, cell_ind: QCACellIndex) -> f64 {
+        self.calculate_all(&[cell_ind], &WorkerPool::default())[0]
+    }
+
+    /// Solves the ground state whenever the input states changed and returns
+    /// the change of the cell polarizations.
+    fn calculate_all(&mut self, cell_indices: &[QCACellIndex], _workers: &WorkerPool) -> Vec<f64> {
+        if self.solved_input_states.as_ref() == Some(&self.input_states) {
+            return vec![0.0; cell_indices.len()];
+        }
+
+        let problem = self.build_problem();
+        let ground_state = problem.ground_state(
+            self.model_settings.energy_tolerance,
+            self.model_settings.max_search_nodes,
+        );
+        if !ground_state.exact {
+            warn!(
+                "Ground state search stopped after {} nodes, the state may not be the ground state",
+                self.model_settings.max_search_nodes
+            );
+        }
+        debug!(
+            "Ground state energy {:e} meV, degeneracy {}",
+            ground_state.energy, ground_state.degeneracy
+        );
+
+        self.polarizations.advance();
+        for (cell_id, polarization) in self.search_order.iter().zip(&ground_state.polarizations) {
+            self.polarizations
+                .set_current(*cell_id, polarization.as_slice());
+        }
+        self.solved_input_states = Some(self.input_states.clone());
+
+        cell_indices
+            .iter()
+            .map(|cell_ind| {
+                let cell_id = self.cell_ids.id(cell_ind);
+                self.polarizations
+                    .current(cell_id)
+                    .iter()
+                    .zip(self.polarizations.previous(cell_id))
+                    .map(|(new, old)| (new - old).abs())
+                    .fold(0.0, f64::max)
+            })
+            .collect()
+    }
+
+    fn get_states(&self, cell_ind: &QCACellIndex) -> Vec<f64> {
+        polarization_to_dot_probability_distribution(
+            self.polarizations.current(self.cell_ids.id(cell_ind)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn energy(problem: &IsingProblem, assignment: &[usize]) -> f64 {
+        let spins: Vec<DVector<f64>> = assignment
+            .iter()
+            .zip(&problem.axes)
+            .map(|(state, axes)| spin_polarization(*state, *axes))
+            .collect();
+        let mut energy = 0.0;
+        for k in 0..spins.len() {
+            energy -= spins[k].dot(&problem.fields[k]);
+            for (j, coupling) in &problem.couplings[k] {
+                energy -= spins[k].dot(&(coupling * &spins[*j]));
+            }
+        }
+        energy
+    }
+
+    #[test]
+    fn test_branch_and_bound_matches_exhaustive_search() {
+        // Deterministic pseudo random problem with mixed two- and four-state spins
+        let mut seed: u64 = 12345;
+        let mut random = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+        };
+        let axes = vec![1, 2, 1, 1, 2, 1, 1, 2];
+        let problem = IsingProblem {
+            fields: axes
+                .iter()
+                .map(|a| DVector::from_fn(*a, |_, _| random()))
+                .collect(),
+            couplings: (0..axes.len())
+                .map(|k| {
+                    (0..k)
+                        .map(|j| (j, DMatrix::from_fn(axes[k], axes[j], |_, _| random())))
+                        .collect()
+                })
+                .collect(),
+            axes,
+        };
+
+        let mut lowest = f64::INFINITY;
+        let mut assignment = vec![0; problem.axes.len()];
+        loop {
+            lowest = lowest.min(energy(&problem, &assignment));
+            let Some(k) = (0..assignment.len()).find(|k| assignment[*k] + 1 < 2 * problem.axes[*k])
+            else {
+                break;
+            };
+            assignment[k] += 1;
+            assignment[..k].iter_mut().for_each(|state| *state = 0);
+        }
+
+        let ground_state = problem.ground_state(1e-9, usize::MAX);
+        assert!(ground_state.exact);
+        assert_eq!(ground_state.degeneracy, 1);
+        assert!((ground_state.energy - lowest).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_degenerate_ground_states_are_averaged() {
+        // Two antiferromagnetically coupled spins without external field
+        let problem = IsingProblem {
+            axes: vec![1, 1],
+            fields: vec![DVector::zeros(1), DVector::zeros(1)],
+            couplings: vec![vec![], vec![(0, DMatrix::from_element(1, 1, -1.0))]],
+        };
+
+        let ground_state = problem.ground_state(1e-9, usize::MAX);
+        assert_eq!(ground_state.degeneracy, 2);
+        assert_eq!(ground_state.energy, -1.0);
+        assert_eq!(ground_state.polarizations[0][0], 0.0);
+    }
+}
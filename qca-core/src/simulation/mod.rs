@@ -2,16 +2,25 @@ use crate::objects::architecture::QCACellArchitecture;
 use crate::objects::cell::{dot_probability_distribution_to_polarization, CellType, QCACellIndex};
 use crate::objects::generator::Generator;
 use crate::objects::layer::QCALayer;
-use crate::simulation::clock_generator::{ClockConfig, ClockGenerator};
-use crate::simulation::file::{QCACellData, QCASimulationData};
-use crate::simulation::input_generator::{CellInputConfig, CellInputGenerator};
-use crate::simulation::model::SimulationModelTrait;
+use crate::simulation::clock_generator::{
+    ClockConfig, ClockGenerator, ClockNoise, ClockPhases, ClockSignal, ClockStates,
+};
+use crate::simulation::file::{
+    QCACellData, QCACellErrorProbability, QCASampleConvergence, QCASimulationData,
+};
+use crate::simulation::input_generator::{
+    CellInputConfig, InputConfig, InputGenerator, InputWaveform,
+};
+use crate::simulation::model::{SimulationModelSettingsTrait, SimulationModelTrait, WorkerPool};
+use crate::simulation::stimulus::{Stimulus, StimulusInputConfig};
 use chrono::Local;
 use log::{debug, info, trace, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use tokio::sync::oneshot;
 
 pub mod model;
@@ -19,10 +28,19 @@ pub mod settings;
 
 //pub mod bistable;
 pub mod bistable;
+pub mod cell_state;
 pub mod clock_generator;
+pub mod coherence_vector;
 pub mod file;
 pub mod icha;
 pub mod input_generator;
+pub mod ising;
+pub mod monte_carlo;
+pub mod neighborhood;
+pub mod qcadesigner;
+pub mod stimulus;
+#[cfg(test)]
+pub(crate) mod test_designs;
 
 #[derive(Debug)]
 pub enum SimulationProgress {
@@ -36,6 +54,45 @@ pub enum SimulationProgress {
 #[derive(Debug)]
 pub struct SimulationCancelRequest {}
 
+/// Alignment of the input changes and the clock cycles of a run.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct SampleTiming {
+    /// Samples per input combination
+    pub samples_per_input: usize,
+    /// Samples per clock cycle
+    pub samples_per_cycle: usize,
+    /// Samples before the first input change, which lag the start of a clock
+    /// cycle by this amount
+    pub input_offset: usize,
+}
+
+/// Options of a simulation run that are independent of the design and model.
+#[derive(Debug, Clone)]
+pub struct SimulationOptions {
+    /// Number of threads used to calculate the cells of an iteration.
+    pub num_threads: usize,
+    /// External clock signal used instead of the model's clock generator.
+    pub clock_signal: Option<ClockSignal>,
+    /// Random perturbations of the clock signals.
+    pub clock_noise: Option<ClockNoise>,
+    /// Shape of the input signals.
+    pub input_waveform: InputWaveform,
+    /// Input sequence used instead of enumerating all input combinations.
+    pub stimulus: Option<Stimulus>,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        SimulationOptions {
+            num_threads: 1,
+            clock_signal: None,
+            clock_noise: None,
+            input_waveform: InputWaveform::default(),
+            stimulus: None,
+        }
+    }
+}
+
 fn send_progress(progress: SimulationProgress, tx: &Option<Sender<SimulationProgress>>) {
     if let Some(tx) = &tx {
         let _ = tx.send(progress);
@@ -49,45 +106,204 @@ fn check_cancelled(rx: &mut Option<oneshot::Receiver<SimulationCancelRequest>>)
     }
 }
 
+/// Configuration of the input and clock generators of a run.
+pub struct GeneratorConfigs {
+    pub input: InputConfig,
+    pub clock: ClockConfig,
+    pub timing: SampleTiming,
+}
+
+/// Builds the input and clock generator configuration of a run from the
+/// model's clock generator settings, the design and the run options.
+pub fn build_generator_configs(
+    sim_model: &dyn SimulationModelTrait,
+    layers: &Vec<QCALayer>,
+    architectures: &HashMap<String, QCACellArchitecture>,
+    options: &SimulationOptions,
+) -> GeneratorConfigs {
+    let architecture = architectures.get(&layers[0].cell_architecture_id).unwrap();
+    let polarization_n = (architecture.dot_count / 4) as usize;
+    //TODO: ugly workaround
+    let num_inputs = get_num_inputs(layers);
+    let clock_generator_settings = sim_model.get_clock_generator_settings();
+
+    // Every polarization axis needs its own extra periods
+    let extra_periods = clock_generator_settings.get_extra_periods() * polarization_n;
+    let input_offset = clock_generator_settings.get_input_offset();
+    let (input, timing) = match &options.stimulus {
+        Some(stimulus) => {
+            // The stimulus is given in clock cycles, each cycle is one input
+            // period
+            let samples_per_cycle = match clock_generator_settings.get_samples_per_cycle() {
+                0 => (clock_generator_settings.get_samples_per_input()
+                    / clock_generator_settings.get_num_cycles().max(1))
+                .max(1),
+                samples_per_cycle => samples_per_cycle,
+            };
+            let input = InputConfig::Stimulus(StimulusInputConfig {
+                input_cycles: stimulus.input_cycles(layers, polarization_n),
+                samples_per_cycle,
+                num_polarization: polarization_n,
+                extra_clock_periods: extra_periods,
+                offset: input_offset,
+                waveform: options.input_waveform,
+            });
+            let timing = SampleTiming {
+                samples_per_input: samples_per_cycle,
+                samples_per_cycle,
+                input_offset,
+            };
+            (input, timing)
+        }
+        None => {
+            let input = InputConfig::Combinations(CellInputConfig {
+                num_inputs,
+                num_samples_per_combination: clock_generator_settings.get_samples_per_input(),
+                num_polarization: polarization_n,
+                extra_clock_periods: extra_periods,
+                offset: input_offset,
+                waveform: options.input_waveform,
+            });
+            let num_samples = InputGenerator::new(input.clone()).num_samples();
+            let timing = SampleTiming {
+                samples_per_input: clock_generator_settings.get_samples_per_input(),
+                samples_per_cycle: match clock_generator_settings.get_samples_per_cycle() {
+                    // Spread the number of cycles per input over its samples
+                    0 => {
+                        let num_cycles = (polarization_n + 1).pow(num_inputs as u32)
+                            * clock_generator_settings.get_num_cycles()
+                            + extra_periods;
+                        (num_samples - input_offset) / num_cycles.max(1)
+                    }
+                    samples_per_cycle => samples_per_cycle,
+                },
+                input_offset,
+            };
+            (input, timing)
+        }
+    };
+    let num_samples = InputGenerator::new(input.clone()).num_samples();
+
+    // A signal with one waveform per zone fixes the zones
+    let phases = match options.clock_signal.as_ref().and_then(|s| s.num_zones()) {
+        Some(count) => {
+            if clock_generator_settings.get_continuous_clock_phase() {
+                warn!("Continuous clock phases are not supported by a clock signal per zone");
+            }
+            ClockPhases::Zones { count }
+        }
+        None if clock_generator_settings.get_continuous_clock_phase() => {
+            ClockPhases::continuous(layers)
+        }
+        None => ClockPhases::Zones {
+            count: clock_generator_settings.get_num_clock_zones(),
+        },
+    };
+    let clock = ClockConfig {
+        num_samples,
+        samples_per_cycle: timing.samples_per_cycle,
+        amplitude_max: clock_generator_settings.get_amplitude_max(),
+        amplitude_min: clock_generator_settings.get_amplitude_min(),
+        phases,
+        waveform: clock_generator_settings.get_waveform(),
+        signal: options.clock_signal.clone(),
+        noise: options.clock_noise,
+    };
+
+    GeneratorConfigs {
+        input,
+        clock,
+        timing,
+    }
+}
+
+fn get_cell_indices(layers: &[QCALayer]) -> Vec<QCACellIndex> {
+    layers
+        .iter()
+        .enumerate()
+        .flat_map(|(l, layer)| (0..layer.cells.len()).map(move |c| QCACellIndex::new(l, c)))
+        .collect()
+}
+
+/// Relaxes the cells of a sample until they are stable or the iteration
+/// limit is reached. Models that do not relax their samples calculate them
+/// once and report no convergence.
+fn relax_sample(
+    sim_model: &mut dyn SimulationModelTrait,
+    model_settings: &dyn SimulationModelSettingsTrait,
+    clock_states: &ClockStates,
+    input_states: &Vec<f64>,
+    cell_indices: &[QCACellIndex],
+    workers: &WorkerPool,
+) -> Option<QCASampleConvergence> {
+    if !model_settings.relaxes_samples() {
+        sim_model.pre_calculate(clock_states, input_states);
+        sim_model.calculate_all(cell_indices, workers);
+        return None;
+    }
+
+    let mut convergence = QCASampleConvergence {
+        iterations: 0,
+        max_residual: 0.0,
+        converged: false,
+    };
+    while !convergence.converged && convergence.iterations < model_settings.get_max_iterations() {
+        sim_model.pre_calculate(clock_states, input_states);
+        let residuals = sim_model.calculate_all(cell_indices, workers);
+
+        convergence.iterations += 1;
+        convergence.max_residual = residuals.into_iter().fold(0.0, f64::max);
+        convergence.converged =
+            convergence.max_residual <= model_settings.get_convergence_tolerance();
+    }
+    Some(convergence)
+}
+
 fn run_simulation_internal(
     mut sim_model: Box<dyn SimulationModelTrait>,
     layers: Vec<QCALayer>,
     architectures: HashMap<String, QCACellArchitecture>,
+    options: SimulationOptions,
     progress_tx: Option<Sender<SimulationProgress>>,
     cancel_rx: &mut Option<oneshot::Receiver<SimulationCancelRequest>>,
 ) -> QCASimulationData {
     info!("Starting simulation");
     send_progress(SimulationProgress::Initializing, &progress_tx);
     let mut simulation_data = QCASimulationData::new();
-    let architecture = architectures.get(&layers[0].cell_architecture_id).unwrap();
-    let polarization_n = architecture.dot_count / 4;
-    //TODO: ugly workaround
-    let num_inputs = get_num_inputs(&layers);
-
     let model_settings = sim_model.get_model_settings();
-    let clock_generator_settings = sim_model.get_clock_generator_settings();
+    let configs = build_generator_configs(sim_model.as_ref(), &layers, &architectures, &options);
+    let timing = configs.timing;
+    let clock_phases = configs.clock.phases.clone();
+    let num_clock_signals = clock_phases.num_signals();
 
-    let input_generator = CellInputGenerator::new(CellInputConfig {
-        num_inputs,
-        num_samples_per_combination: clock_generator_settings.get_samples_per_input(),
-        num_polarization: polarization_n as usize,
-        extra_clock_periods: clock_generator_settings.get_extra_periods() * polarization_n as usize,
-    });
+    let input_generator = InputGenerator::new(configs.input);
     let mut input_iter = input_generator.iter();
     let num_samples = input_generator.num_samples();
-    let clock_generator = ClockGenerator::new(ClockConfig {
-        num_samples,
-        num_cycles: (polarization_n as usize + 1).pow(num_inputs as u32)
-            * clock_generator_settings.get_num_cycles()
-            + (polarization_n as usize * clock_generator_settings.get_extra_periods()),
-        amplitude_max: clock_generator_settings.get_amplitude_max(),
-        amplitude_min: clock_generator_settings.get_amplitude_min(),
-    });
+    let clock_generator = ClockGenerator::new(configs.clock);
     let mut clock_iter = clock_generator.iter();
+    simulation_data.metadata.num_clock_zones = num_clock_signals;
+    simulation_data.metadata.clock_phase_shifts = match &clock_phases {
+        ClockPhases::Zones { .. } => None,
+        ClockPhases::Continuous { phase_shifts } => Some(phase_shifts.clone()),
+    };
+    simulation_data.metadata.clock_signal = options.clock_signal.clone();
+    simulation_data.metadata.clock_noise = options.clock_noise;
+    simulation_data.metadata.input_waveform = options.input_waveform;
+    simulation_data.metadata.stimulus = options.stimulus.clone();
+    simulation_data.metadata.timing = Some(timing);
+    simulation_data.clock_data = vec![Vec::with_capacity(num_samples); num_clock_signals];
 
     for i in 0..layers.len() {
         for j in 0..layers[i].cells.len() {
             let cell = &layers[i].cells[j];
+            if cell.typ == CellType::Output {
+                simulation_data
+                    .error_probabilities
+                    .push(QCACellErrorProbability {
+                        index: QCACellIndex::new(i, j),
+                        data: Vec::new(),
+                    });
+            }
             if matches!(cell.typ, CellType::Input | CellType::Output) {
                 let cell_index = QCACellIndex::new(i, j);
                 simulation_data
@@ -101,10 +317,18 @@ fn run_simulation_internal(
         }
     }
 
+    let cell_indices = get_cell_indices(&layers);
+
     debug!("Simulation model: {:?}", sim_model.get_name());
     debug!("Total iterations: {:?}", num_samples);
+    debug!("Update scheme: {:?}", model_settings.get_update_scheme());
+    if model_settings.get_update_scheme().uses_latest_states() && options.num_threads > 1 {
+        warn!("Gauss-Seidel updates are calculated on a single thread");
+    }
 
     sim_model.initiate(Box::new(layers.clone()), architectures.clone());
+    let workers = WorkerPool::new(options.num_threads);
+    debug!("Threads: {:?}", workers.num_threads());
 
     let mut simulated_samples: usize = 0;
     for i in 0..num_samples {
@@ -121,30 +345,32 @@ fn run_simulation_internal(
             &progress_tx,
         );
 
-        let clock_states = clock_iter.next().unwrap();
+        let clock_states = ClockStates {
+            phases: clock_phases.clone(),
+            values: clock_iter.next().unwrap(),
+        };
         let input_states = input_iter.next().unwrap();
 
-        trace!("Clock states: {:?}", clock_states);
+        trace!("Clock states: {:?}", clock_states.values);
         trace!("Input states: {:?}", input_states);
 
-        let mut stable = false;
-        let mut j = 0;
-        while !stable && j < model_settings.get_max_iterations() {
-            stable = true;
-
-            sim_model.pre_calculate(&clock_states, &input_states);
+        let convergence = relax_sample(
+            sim_model.as_mut(),
+            model_settings.as_ref(),
+            &clock_states,
+            &input_states,
+            &cell_indices,
+            &workers,
+        );
 
-            for l in 0..layers.len() {
-                for c in 0..layers[l].cells.len() {
-                    stable &= sim_model.calculate(QCACellIndex::new(l, c));
-                }
+        if let Some(convergence) = convergence {
+            if !convergence.converged {
+                warn!(
+                    "Unstable simulation loop detected at sample {}, residual {:e}",
+                    i, convergence.max_residual
+                )
             }
-
-            j += 1;
-        }
-
-        if !stable {
-            warn!("Unstable simulation loop detected")
+            simulation_data.convergence_data.push(convergence);
         }
 
         simulation_data
@@ -152,7 +378,7 @@ fn run_simulation_internal(
             .iter_mut()
             .enumerate()
             .for_each(|(i, clock_data)| {
-                clock_data.push(clock_states[i]);
+                clock_data.push(clock_states.values[i]);
             });
 
         simulation_data.cells_data.iter_mut().for_each(|cell_data| {
@@ -162,13 +388,36 @@ fn run_simulation_internal(
                 cell_data.data.push(p);
             }
         });
+        simulation_data
+            .error_probabilities
+            .iter_mut()
+            .for_each(|error_probability| {
+                if let Some(probability) = sim_model.get_error_probability(&error_probability.index)
+                {
+                    error_probability.data.push(probability);
+                }
+            });
         simulated_samples += 1;
     }
     send_progress(SimulationProgress::Deinitializng, &progress_tx);
+    simulation_data
+        .error_probabilities
+        .retain(|error_probability| !error_probability.data.is_empty());
     simulation_data.metadata.duration = Local::now() - simulation_data.metadata.start_time;
     simulation_data.metadata.num_samples = simulated_samples;
 
     info!("Simulation complete");
+    let unconverged_samples = simulation_data
+        .convergence_data
+        .iter()
+        .filter(|convergence| !convergence.converged)
+        .count();
+    if unconverged_samples > 0 {
+        warn!(
+            "{} of {} samples did not converge",
+            unconverged_samples, simulated_samples
+        );
+    }
     debug!("Duration: {:?}", simulation_data.metadata.duration);
     debug!(
         "Number of samples: {}",
@@ -182,14 +431,16 @@ pub fn run_simulation(
     sim_model: Box<dyn SimulationModelTrait>,
     layers: Vec<QCALayer>,
     architectures: HashMap<String, QCACellArchitecture>,
+    options: SimulationOptions,
 ) -> QCASimulationData {
-    run_simulation_internal(sim_model, layers, architectures, None, &mut None)
+    run_simulation_internal(sim_model, layers, architectures, options, None, &mut None)
 }
 
 pub fn run_simulation_async(
     sim_model: Box<dyn SimulationModelTrait>,
     layers: Vec<QCALayer>,
     architectures: HashMap<String, QCACellArchitecture>,
+    options: SimulationOptions,
 ) -> (
     JoinHandle<QCASimulationData>,
     Receiver<SimulationProgress>,
@@ -202,6 +453,7 @@ pub fn run_simulation_async(
             sim_model,
             layers,
             architectures,
+            options,
             Some(progress_tx),
             &mut Some(cancel_rx),
         );
@@ -223,23 +475,298 @@ pub fn get_num_inputs(layers: &Vec<QCALayer>) -> usize {
         .sum()
 }
 
+/// Number of samples of a run of the design with `options`.
 pub fn get_num_samples(
     sim_model: &Box<dyn SimulationModelTrait>,
     layers: &Vec<QCALayer>,
     architectures: &HashMap<String, QCACellArchitecture>,
+    options: &SimulationOptions,
 ) -> usize {
-    let architecture = architectures.get(&layers[0].cell_architecture_id).unwrap();
-    let polarization_n = architecture.dot_count / 4;
-    let clock_generator_settings = sim_model.get_clock_generator_settings();
+    build_generator_configs(sim_model.as_ref(), layers, architectures, options)
+        .clock
+        .num_samples
+}
 
-    let num_inputs = get_num_inputs(layers);
+/// Pre-run estimate of the size and duration of a simulation.
+#[derive(Debug, Clone)]
+pub struct SimulationEstimate {
+    /// Number of samples of the run
+    pub num_samples: usize,
+    /// Number of samples simulated to calibrate the estimate
+    pub calibration_samples: usize,
+    /// Average relaxation iterations per calibration sample, 1 for models
+    /// that do not relax their samples
+    pub mean_iterations: f64,
+    /// Estimated duration of the full run
+    pub duration: Duration,
+}
 
-    let input_generator = CellInputGenerator::new(CellInputConfig {
-        num_inputs,
-        num_samples_per_combination: clock_generator_settings.get_samples_per_input(),
-        num_polarization: polarization_n as usize,
-        extra_clock_periods: clock_generator_settings.get_extra_periods(),
-    });
+/// Estimates a run by simulating its first clock cycle and extrapolating the
+/// time per sample to all samples.
+pub fn estimate_simulation(
+    mut sim_model: Box<dyn SimulationModelTrait>,
+    layers: Vec<QCALayer>,
+    architectures: HashMap<String, QCACellArchitecture>,
+    options: SimulationOptions,
+) -> SimulationEstimate {
+    let model_settings = sim_model.get_model_settings();
+    let configs = build_generator_configs(sim_model.as_ref(), &layers, &architectures, &options);
+    let num_samples = configs.clock.num_samples;
+    let calibration_samples = configs
+        .timing
+        .samples_per_cycle
+        .clamp(1, num_samples.max(1));
+    let clock_phases = configs.clock.phases.clone();
+    let clock_generator = ClockGenerator::new(configs.clock);
+    let input_generator = InputGenerator::new(configs.input);
+    let cell_indices = get_cell_indices(&layers);
+
+    let setup_start = Instant::now();
+    sim_model.initiate(Box::new(layers), architectures);
+    let workers = WorkerPool::new(options.num_threads);
+    let setup_duration = setup_start.elapsed();
+
+    let calibration_start = Instant::now();
+    let mut iterations = 0;
+    let mut simulated_samples = 0;
+    for (clock_values, input_states) in clock_generator
+        .iter()
+        .zip(input_generator.iter())
+        .take(calibration_samples)
+    {
+        let clock_states = ClockStates {
+            phases: clock_phases.clone(),
+            values: clock_values,
+        };
+        iterations += relax_sample(
+            sim_model.as_mut(),
+            model_settings.as_ref(),
+            &clock_states,
+            &input_states,
+            &cell_indices,
+            &workers,
+        )
+        .map_or(1, |convergence| convergence.iterations);
+        simulated_samples += 1;
+    }
+    let sample_duration = calibration_start.elapsed() / simulated_samples.max(1) as u32;
 
-    input_generator.num_samples()
+    SimulationEstimate {
+        num_samples,
+        calibration_samples: simulated_samples,
+        mean_iterations: iterations as f64 / simulated_samples.max(1) as f64,
+        duration: setup_duration
+            + Duration::from_secs_f64(sample_duration.as_secs_f64() * num_samples as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::bistable::BistableModel;
+    use crate::simulation::model::ClockGeneratorSettingsTrait;
+    use crate::simulation::settings::OptionsList;
+    use crate::simulation::test_designs::tri_state_wire;
+
+    /// Model returning scripted residuals for every iteration.
+    struct ScriptedModel {
+        residuals: Vec<Vec<f64>>,
+        iterations: usize,
+    }
+
+    struct ScriptedSettings {
+        max_iterations: usize,
+        convergence_tolerance: f64,
+        relaxes_samples: bool,
+    }
+
+    impl SimulationModelSettingsTrait for ScriptedSettings {
+        fn get_max_iterations(&self) -> usize {
+            self.max_iterations
+        }
+        fn get_convergence_tolerance(&self) -> f64 {
+            self.convergence_tolerance
+        }
+        fn relaxes_samples(&self) -> bool {
+            self.relaxes_samples
+        }
+    }
+
+    impl SimulationModelTrait for ScriptedModel {
+        fn get_name(&self) -> String {
+            "Scripted".into()
+        }
+        fn get_unique_id(&self) -> String {
+            "scripted".into()
+        }
+        fn get_model_settings(&self) -> Box<dyn SimulationModelSettingsTrait> {
+            unimplemented!()
+        }
+        fn get_clock_generator_settings(&self) -> Box<dyn ClockGeneratorSettingsTrait> {
+            unimplemented!()
+        }
+        fn get_model_options_list(&self) -> OptionsList {
+            vec![]
+        }
+        fn get_clock_generator_options_list(&self) -> OptionsList {
+            vec![]
+        }
+        fn serialize_model_settings(&self) -> Result<String, String> {
+            unimplemented!()
+        }
+        fn deserialize_model_settings(&mut self, _settings_str: &String) -> Result<(), String> {
+            unimplemented!()
+        }
+        fn serialize_clock_generator_settings(&self) -> Result<String, String> {
+            unimplemented!()
+        }
+        fn deserialize_clock_generator_settings(
+            &mut self,
+            _settings_str: &String,
+        ) -> Result<(), String> {
+            unimplemented!()
+        }
+        fn initiate(
+            &mut self,
+            _layers: Box<Vec<QCALayer>>,
+            _qca_architetures_map: HashMap<String, QCACellArchitecture>,
+        ) {
+        }
+        fn pre_calculate(&mut self, _clock_states: &ClockStates, _input_states: &Vec<f64>) {}
+        fn calculate(&mut self, _cell_index: QCACellIndex) -> f64 {
+            unimplemented!()
+        }
+        fn calculate_all(
+            &mut self,
+            _cell_indices: &[QCACellIndex],
+            _workers: &WorkerPool,
+        ) -> Vec<f64> {
+            self.iterations += 1;
+            self.residuals[self.iterations - 1].clone()
+        }
+        fn get_states(&self, _cell_index: &QCACellIndex) -> Vec<f64> {
+            unimplemented!()
+        }
+    }
+
+    /// Convergence of a sample relaxed with the scripted residuals, and the
+    /// number of iterations the model calculated.
+    fn relax(settings: ScriptedSettings) -> (Option<QCASampleConvergence>, usize) {
+        let mut model = ScriptedModel {
+            residuals: vec![
+                vec![0.5, 0.1],
+                vec![0.01, 0.2],
+                vec![1e-7, 0.0],
+                vec![9.0, 9.0],
+            ],
+            iterations: 0,
+        };
+        let convergence = relax_sample(
+            &mut model,
+            &settings,
+            &ClockStates::default(),
+            &vec![],
+            &[QCACellIndex::new(0, 0), QCACellIndex::new(0, 1)],
+            &WorkerPool::default(),
+        );
+        (convergence, model.iterations)
+    }
+
+    #[test]
+    fn test_relax_sample_stops_at_tolerance_or_iteration_limit() {
+        let (convergence, iterations) = relax(ScriptedSettings {
+            max_iterations: 10,
+            convergence_tolerance: 1e-6,
+            relaxes_samples: true,
+        });
+        let convergence = convergence.unwrap();
+        assert_eq!(iterations, 3);
+        assert_eq!(convergence.iterations, 3);
+        assert_eq!(convergence.max_residual, 1e-7);
+        assert!(convergence.converged);
+
+        // A residual equal to the tolerance is converged
+        let (convergence, _) = relax(ScriptedSettings {
+            max_iterations: 10,
+            convergence_tolerance: 0.2,
+            relaxes_samples: true,
+        });
+        let convergence = convergence.unwrap();
+        assert_eq!(convergence.iterations, 2);
+        assert!(convergence.converged);
+
+        let (convergence, iterations) = relax(ScriptedSettings {
+            max_iterations: 2,
+            convergence_tolerance: 1e-6,
+            relaxes_samples: true,
+        });
+        let convergence = convergence.unwrap();
+        assert_eq!(iterations, 2);
+        assert_eq!(convergence.iterations, 2);
+        assert_eq!(convergence.max_residual, 0.2);
+        assert!(!convergence.converged);
+
+        let (convergence, iterations) = relax(ScriptedSettings {
+            max_iterations: 10,
+            convergence_tolerance: 1e-6,
+            relaxes_samples: false,
+        });
+        assert!(convergence.is_none());
+        assert_eq!(iterations, 1);
+    }
+
+    /// Every polarization axis of an 8-dot cell runs its own extra periods,
+    /// which the sample count has to include.
+    #[test]
+    fn test_extra_periods_per_polarization_axis() {
+        let (layers, architectures) = tri_state_wire(3);
+        let mut model = BistableModel::new();
+        model
+            .deserialize_clock_generator_settings(
+                &r#"{"samples_per_input": 10, "extra_periods": 2}"#.to_string(),
+            )
+            .unwrap();
+        let model: Box<dyn SimulationModelTrait> = Box::new(model);
+
+        // Three input combinations, then 2 extra periods per axis
+        let num_samples = get_num_samples(
+            &model,
+            &layers,
+            &architectures,
+            &SimulationOptions::default(),
+        );
+        assert_eq!(num_samples, (3 + 2 * 2) * 10);
+        let simulation = run_simulation(model, layers, architectures, SimulationOptions::default());
+        assert_eq!(simulation.metadata.num_samples, num_samples);
+    }
+
+    #[test]
+    fn test_estimate_simulation() {
+        let (layers, architectures) = tri_state_wire(3);
+        let options = || SimulationOptions {
+            stimulus: Some(
+                Stimulus::from_json(r#"{"inputs": {"0-0": [{"value": [1, 0], "hold": 3}]}}"#)
+                    .unwrap(),
+            ),
+            ..SimulationOptions::default()
+        };
+
+        let estimate = estimate_simulation(
+            Box::new(BistableModel::new()),
+            layers.clone(),
+            architectures.clone(),
+            options(),
+        );
+        let model: Box<dyn SimulationModelTrait> = Box::new(BistableModel::new());
+        assert_eq!(
+            estimate.num_samples,
+            get_num_samples(&model, &layers, &architectures, &options())
+        );
+        let simulation = run_simulation(model, layers, architectures, options());
+        assert_eq!(estimate.num_samples, simulation.metadata.num_samples);
+        // The first clock cycle calibrates the estimate
+        assert_eq!(estimate.calibration_samples, 20);
+        assert!(estimate.mean_iterations >= 1.0);
+        assert!(estimate.duration > Duration::ZERO);
+    }
 }
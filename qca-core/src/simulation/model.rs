@@ -1,12 +1,174 @@
 use crate::objects::architecture::QCACellArchitecture;
 use crate::objects::cell::QCACellIndex;
 use crate::objects::layer::QCALayer;
-use crate::simulation::settings::OptionsList;
+use crate::simulation::clock_generator::{
+    clock_waveform_options, ClockShape, ClockStates, ClockWaveform, DEFAULT_CLOCK_ZONE_COUNT,
+};
+use crate::simulation::settings::{InputDescriptor, OptionsEntry, OptionsList, SelectOption};
+use log::warn;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+pub const K_B: f64 = 8.617_333_262e-2; // Boltzmann constant [meV/K]
+
+/// How the new cell states of an iteration are combined with the old ones.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateScheme {
+    /// Every cell is updated from the states of the previous iteration.
+    Jacobi,
+    /// Cells are updated in order and already see the new states of the
+    /// preceding cells. Always runs on a single thread.
+    GaussSeidel,
+    /// Jacobi updates that only move a cell part of the way towards its new
+    /// state (under-relaxation).
+    Damped,
+}
+
+impl UpdateScheme {
+    /// Whether a cell update sees the states calculated earlier in the same
+    /// iteration.
+    pub fn uses_latest_states(&self) -> bool {
+        matches!(self, UpdateScheme::GaussSeidel)
+    }
+
+    /// Fraction of the way a cell moves towards its newly calculated state.
+    pub fn relaxation_factor(&self, damping_factor: f64) -> f64 {
+        match self {
+            UpdateScheme::Damped => damping_factor,
+            UpdateScheme::Jacobi | UpdateScheme::GaussSeidel => 1.0,
+        }
+    }
+}
+
+/// Options entries for the update scheme and damping factor settings, shared
+/// by the models.
+pub fn update_scheme_options() -> OptionsList {
+    vec![
+        OptionsEntry::Input {
+            unique_id: "update_scheme".into(),
+            name: "Update scheme".into(),
+            description: "How the new cell states of an iteration are applied".into(),
+            descriptor: InputDescriptor::SelectInput {
+                options: vec![
+                    SelectOption {
+                        value: "jacobi".into(),
+                        label: "Jacobi".into(),
+                    },
+                    SelectOption {
+                        value: "gauss_seidel".into(),
+                        label: "Gauss-Seidel".into(),
+                    },
+                    SelectOption {
+                        value: "damped".into(),
+                        label: "Damped (under-relaxation)".into(),
+                    },
+                ],
+            },
+        },
+        OptionsEntry::Input {
+            unique_id: "damping_factor".into(),
+            name: "Damping factor".into(),
+            description: "Fraction of the change applied per iteration by damped updates".into(),
+            descriptor: InputDescriptor::NumberInput {
+                min: Some(0.0),
+                max: Some(1.0),
+                unit: None,
+                whole_num: false,
+            },
+        },
+    ]
+}
+
+/// Options entries for the clock generator settings, shared by the models.
+/// Models that ignore the clock leave out the amplitude settings by passing
+/// `amplitudes: false`.
+pub fn clock_generator_options(amplitudes: bool) -> OptionsList {
+    let amplitude = |unique_id: &str, name: &str, bound: &str| OptionsEntry::Input {
+        unique_id: unique_id.into(),
+        name: name.into(),
+        description: format!("The {} energy of the clock signal", bound),
+        descriptor: InputDescriptor::NumberInput {
+            min: None,
+            max: None,
+            unit: Some("meV".into()),
+            whole_num: false,
+        },
+    };
+
+    [
+        vec![
+            OptionsEntry::Input {
+                unique_id: "num_cycles".into(),
+                name: "Number of cycles".into(),
+                description: "The number of repeating clock cycles to run".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(1.0),
+                    max: None,
+                    unit: None,
+                    whole_num: true,
+                },
+            },
+            amplitude("amplitude_min", "Minimum amplitude", "minimum"),
+            amplitude("amplitude_max", "Maximum amplitude", "maximum"),
+            OptionsEntry::Input {
+                unique_id: "extra_periods".into(),
+                name: "Extra periods".into(),
+                description: "Extra clock periods at the end to account for delays".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: None,
+                    whole_num: true,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "samples_per_input".into(),
+                name: "Samples per input".into(),
+                description: "Number of samples to be simulated for each input combination".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(1.0),
+                    max: None,
+                    unit: None,
+                    whole_num: true,
+                },
+            },
+        ],
+        clock_waveform_options(),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|entry| {
+        amplitudes
+            || !matches!(entry, OptionsEntry::Input { unique_id, .. }
+                if unique_id.starts_with("amplitude_"))
+    })
+    .collect()
+}
+
+/// Iteration settings of a model. The defaults describe a model that
+/// calculates every sample in a single pass, relaxing models override all of
+/// them.
 pub trait SimulationModelSettingsTrait {
-    fn get_max_iterations(&self) -> usize;
-    fn get_convergence_tolerance(&self) -> f64;
+    fn get_max_iterations(&self) -> usize {
+        1
+    }
+    fn get_convergence_tolerance(&self) -> f64 {
+        f64::INFINITY
+    }
+    fn get_update_scheme(&self) -> UpdateScheme {
+        UpdateScheme::Jacobi
+    }
+    fn get_damping_factor(&self) -> f64 {
+        1.0
+    }
+    /// Whether the cells of every sample are relaxed until they are stable.
+    /// Models that calculate a sample in a single pass are left out of the
+    /// convergence check.
+    fn relaxes_samples(&self) -> bool {
+        true
+    }
 }
 
 pub trait ClockGeneratorSettingsTrait {
@@ -15,6 +177,111 @@ pub trait ClockGeneratorSettingsTrait {
     fn get_amplitude_max(&self) -> f64;
     fn get_extra_periods(&self) -> usize;
     fn get_samples_per_input(&self) -> usize;
+    /// Samples per clock cycle, 0 spreads the number of cycles over the
+    /// samples of every input combination
+    fn get_samples_per_cycle(&self) -> usize;
+    /// Samples by which input changes lag the start of a clock cycle
+    fn get_input_offset(&self) -> usize;
+    fn get_num_clock_zones(&self) -> usize;
+    fn get_continuous_clock_phase(&self) -> bool;
+    fn get_waveform(&self) -> ClockWaveform;
+}
+
+/// Clock generator settings shared by all models. Each model has its own
+/// defaults, which fill in the settings missing from a deserialized one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClockGeneratorSettings {
+    pub num_cycles: usize,
+    pub amplitude_min: f64,
+    pub amplitude_max: f64,
+    pub extra_periods: usize,
+    pub samples_per_input: usize,
+    pub samples_per_cycle: usize,
+    pub input_offset: usize,
+    pub num_clock_zones: usize,
+    pub continuous_clock_phase: bool,
+    pub clock_shape: ClockShape,
+    pub ramp_fraction: f64,
+    pub hold_fraction: f64,
+    pub amplitude_factor: f64,
+}
+
+impl Default for ClockGeneratorSettings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl ClockGeneratorSettings {
+    /// Defaults of the Bistable, Ising and Monte Carlo models, the other
+    /// models override some of them.
+    pub const DEFAULT: Self = ClockGeneratorSettings {
+        num_cycles: 1,
+        amplitude_min: 1e-3,
+        amplitude_max: 65.0,
+        extra_periods: 0,
+        samples_per_input: 20,
+        samples_per_cycle: 0,
+        input_offset: 0,
+        num_clock_zones: DEFAULT_CLOCK_ZONE_COUNT,
+        continuous_clock_phase: false,
+        clock_shape: ClockShape::RaisedCosine,
+        ramp_fraction: 0.25,
+        hold_fraction: 0.25,
+        amplitude_factor: 0.5,
+    };
+
+    /// Settings from a JSON object, taking the settings it does not contain
+    /// from `defaults`.
+    pub fn from_json(settings_str: &str, defaults: &Self) -> Result<Self, String> {
+        let mut settings = serde_json::to_value(defaults).map_err(|err| err.to_string())?;
+        match serde_json::from_str(settings_str).map_err(|err| err.to_string())? {
+            serde_json::Value::Object(overrides) => settings
+                .as_object_mut()
+                .ok_or("Invalid default clock generator settings")?
+                .extend(overrides),
+            _ => return Err("Clock generator settings must be a JSON object".into()),
+        }
+        serde_json::from_value(settings).map_err(|err| err.to_string())
+    }
+}
+
+impl ClockGeneratorSettingsTrait for ClockGeneratorSettings {
+    fn get_num_cycles(&self) -> usize {
+        self.num_cycles
+    }
+    fn get_amplitude_min(&self) -> f64 {
+        self.amplitude_min
+    }
+    fn get_amplitude_max(&self) -> f64 {
+        self.amplitude_max
+    }
+    fn get_extra_periods(&self) -> usize {
+        self.extra_periods
+    }
+    fn get_samples_per_input(&self) -> usize {
+        self.samples_per_input
+    }
+    fn get_samples_per_cycle(&self) -> usize {
+        self.samples_per_cycle
+    }
+    fn get_input_offset(&self) -> usize {
+        self.input_offset
+    }
+    fn get_num_clock_zones(&self) -> usize {
+        self.num_clock_zones
+    }
+    fn get_continuous_clock_phase(&self) -> bool {
+        self.continuous_clock_phase
+    }
+    fn get_waveform(&self) -> ClockWaveform {
+        ClockWaveform {
+            shape: self.clock_shape,
+            ramp_fraction: self.ramp_fraction,
+            hold_fraction: self.hold_fraction,
+            amplitude_factor: self.amplitude_factor,
+        }
+    }
 }
 
 pub trait SimulationModelTrait: Sync + Send {
@@ -35,8 +302,124 @@ pub trait SimulationModelTrait: Sync + Send {
         layers: Box<Vec<QCALayer>>,
         qca_architetures_map: HashMap<String, QCACellArchitecture>,
     );
-    fn pre_calculate(&mut self, clock_states: &[f64; 4], input_states: &Vec<f64>);
-    fn calculate(&mut self, cell_index: QCACellIndex) -> bool;
+    fn pre_calculate(&mut self, clock_states: &ClockStates, input_states: &Vec<f64>);
+    /// Calculates the new state of a cell and returns its residual, the
+    /// largest change of a state value. Cells that are not calculated, such
+    /// as inputs, have a residual of 0.
+    fn calculate(&mut self, cell_index: QCACellIndex) -> f64;
+    /// Calculates one iteration for all given cells and returns their
+    /// residuals in the same order. Models whose cell updates within an
+    /// iteration are independent can spread them over the `workers`.
+    fn calculate_all(&mut self, cell_indices: &[QCACellIndex], _workers: &WorkerPool) -> Vec<f64> {
+        cell_indices
+            .iter()
+            .map(|cell_index| self.calculate(cell_index.clone()))
+            .collect()
+    }
 
     fn get_states(&self, cell_index: &QCACellIndex) -> Vec<f64>;
+
+    /// Probability that a cell was in a wrong state during the last
+    /// sample, `None` for models that do not estimate it.
+    fn get_error_probability(&self, _cell_index: &QCACellIndex) -> Option<f64> {
+        None
+    }
+}
+
+/// Worker threads of a simulation run, created once and shared by the cell
+/// updates of all its iterations. A single worker runs the updates on the
+/// calling thread.
+pub struct WorkerPool {
+    pool: Option<rayon::ThreadPool>,
+}
+
+impl WorkerPool {
+    pub fn new(num_threads: usize) -> Self {
+        let pool = match num_threads {
+            0 | 1 => None,
+            num_threads => match rayon::ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+            {
+                Ok(pool) => Some(pool),
+                Err(err) => {
+                    warn!("Could not start {} worker threads: {}", num_threads, err);
+                    None
+                }
+            },
+        };
+        WorkerPool { pool }
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.pool
+            .as_ref()
+            .map_or(1, |pool| pool.current_num_threads())
+    }
+
+    /// Maps `f` over `items` on the worker threads, keeping the order of the
+    /// results.
+    pub fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> R + Sync + Send,
+    {
+        match &self.pool {
+            Some(pool) if items.len() > 1 => pool.install(|| items.par_iter().map(f).collect()),
+            _ => items.iter().map(f).collect(),
+        }
+    }
+}
+
+impl Default for WorkerPool {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_generator_settings_defaults() {
+        let defaults = ClockGeneratorSettings {
+            num_cycles: 2,
+            clock_shape: ClockShape::Sinusoidal,
+            ..ClockGeneratorSettings::default()
+        };
+        let settings = ClockGeneratorSettings::from_json(
+            r#"{"num_cycles": 3, "extra_periods": 1}"#,
+            &defaults,
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            ClockGeneratorSettings {
+                num_cycles: 3,
+                extra_periods: 1,
+                ..defaults.clone()
+            }
+        );
+        assert!(ClockGeneratorSettings::from_json("[]", &defaults).is_err());
+        assert!(ClockGeneratorSettings::from_json(r#"{"num_cycles": -1}"#, &defaults).is_err());
+    }
+
+    #[test]
+    fn test_clock_generator_options_without_amplitudes() {
+        let ids = |amplitudes| {
+            clock_generator_options(amplitudes)
+                .into_iter()
+                .filter_map(|entry| match entry {
+                    OptionsEntry::Input { unique_id, .. } => Some(unique_id),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert!(ids(true).contains(&"amplitude_max".to_string()));
+        assert!(ids(false).iter().all(|id| !id.starts_with("amplitude_")));
+        assert_eq!(ids(true).len(), ids(false).len() + 3);
+    }
 }
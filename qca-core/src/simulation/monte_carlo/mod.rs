@@ -0,0 +1,580 @@
+use super::{CellType, QCACellArchitecture, SimulationModelTrait};
+use crate::objects::cell::{
+    dot_probability_distribution_to_polarization, polarization_to_dot_probability_distribution,
+    QCACell, QCACellIndex,
+};
+use crate::objects::layer::QCALayer;
+use crate::simulation::bistable::{kink_energy_neighborhood, BistableNeighbor};
+use crate::simulation::cell_state::{CellIds, CellStates};
+use crate::simulation::clock_generator::ClockStates;
+use crate::simulation::ising::IsingModel;
+use crate::simulation::model::{
+    clock_generator_options, ClockGeneratorSettings, ClockGeneratorSettingsTrait,
+    SimulationModelSettingsTrait, UpdateScheme, WorkerPool, K_B,
+};
+use crate::simulation::settings::{InputDescriptor, OptionsEntry, OptionsList};
+use nalgebra::DVector;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use std::collections::HashMap;
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MonteCarloModelSettings {
+    #[serde_inline_default(1.0)]
+    temperature: f64,
+
+    #[serde_inline_default(100)]
+    sweeps_per_sample: usize,
+
+    #[serde_inline_default(0)]
+    seed: u64,
+
+    #[serde_inline_default(65.0)]
+    neighborhood_radius: f64,
+
+    #[serde_inline_default(12.9)]
+    relative_permitivity: f64,
+}
+
+impl MonteCarloModelSettings {
+    pub fn new() -> Self {
+        serde_json::from_str::<MonteCarloModelSettings>("{}").unwrap()
+    }
+
+    /// Settings of the Ising model solving the reference ground state.
+    fn ising_settings(&self) -> String {
+        serde_json::json!({
+            "neighborhood_radius": self.neighborhood_radius,
+            "relative_permitivity": self.relative_permitivity,
+        })
+        .to_string()
+    }
+}
+
+impl Default for MonteCarloModelSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Every iteration runs all sweeps of a sample, updating one cell after the
+/// other.
+impl SimulationModelSettingsTrait for MonteCarloModelSettings {
+    fn get_update_scheme(&self) -> UpdateScheme {
+        UpdateScheme::GaussSeidel
+    }
+    fn relaxes_samples(&self) -> bool {
+        false
+    }
+}
+
+/// Polarization of state `state` of a cell with `axes` polarization axes:
+/// one fully polarized state per axis and sign, followed by the null state.
+fn state_polarization(state: usize, axes: usize) -> DVector<f64> {
+    let mut polarization = DVector::<f64>::zeros(axes);
+    if state < 2 * axes {
+        polarization[state % axes] = if state < axes { 1.0 } else { -1.0 };
+    }
+    polarization
+}
+
+/// Energy (in meV) of a cell in `state` for the field `Σ E_k P` of its
+/// neighbours. The clock energy is the cost of a polarized state over the
+/// null state, so raising the clock depolarizes the cell like the tunneling
+/// energy does in the bistable cell response.
+fn state_energy(state: usize, field: &DVector<f64>, clock_energy: f64) -> f64 {
+    let axes = field.len();
+    if state == 2 * axes {
+        0.0
+    } else {
+        clock_energy - state_polarization(state, axes).dot(field) / 2.0
+    }
+}
+
+/// Whether a cell polarized as `polarization` is in a wrong state: a
+/// polarized state farther than 1 from the `reference` polarization of the
+/// ground state, averaged over its degenerate states. The null state is
+/// never wrong, and neither is any state of a cell whose degenerate ground
+/// states cancel.
+fn is_wrong(polarization: &[f64], reference: &[f64]) -> bool {
+    polarization.iter().any(|p| *p != 0.0)
+        && polarization
+            .iter()
+            .zip(reference)
+            .map(|(p, r)| (p - r).powi(2))
+            .sum::<f64>()
+            > 1.0 + 1e-9
+}
+
+/// Stochastic model running Metropolis Monte Carlo sweeps over discrete cell
+/// states with the bistable neighbour couplings. The states carry over from
+/// one sample to the next, so the clock anneals the design as in a real
+/// switching event, and every sample reports the polarization averaged over
+/// its sweeps. The error probability of a cell is the fraction of the sweeps
+/// of a sample it spent polarized against the ground state of the design for
+/// the current inputs, which the Ising model solves. It thus counts the
+/// thermal errors as well as wrong states the clock froze in, the switching
+/// errors deterministic relaxation cannot show.
+pub struct MonteCarloModel {
+    clock_states: ClockStates,
+    model_settings: MonteCarloModelSettings,
+    clock_generator_settings: ClockGeneratorSettings,
+    rng: SmallRng,
+    cell_ids: CellIds,
+    cells: Vec<QCACell>,
+    //Instantaneous polarization of every cell
+    polarizations: CellStates,
+    //State index of every dynamic cell, `None` for input and fixed cells
+    cell_states: Vec<Option<usize>>,
+    //Polarization of every cell averaged over the sweeps of the sample
+    average_polarizations: CellStates,
+    //Fraction of the sweeps of the sample every cell spent in a wrong state
+    error_probabilities: Vec<f64>,
+    //Ground state of the design for the current inputs
+    ground_state: IsingModel,
+    //Cell id and input index of every input cell
+    input_cells: Vec<(usize, usize)>,
+    neighborhood: Vec<Vec<BistableNeighbor>>,
+}
+
+impl MonteCarloModel {
+    pub fn new() -> Self {
+        MonteCarloModel {
+            clock_states: ClockStates::default(),
+            model_settings: MonteCarloModelSettings::new(),
+            clock_generator_settings: ClockGeneratorSettings::default(),
+            rng: SmallRng::seed_from_u64(0),
+            cell_ids: CellIds::new(&[]),
+            cells: vec![],
+            polarizations: CellStates::new(&[]),
+            cell_states: vec![],
+            average_polarizations: CellStates::new(&[]),
+            error_probabilities: vec![],
+            ground_state: IsingModel::new(),
+            input_cells: vec![],
+            neighborhood: vec![],
+        }
+    }
+
+    /// One Metropolis update of a dynamic cell: proposes a random other
+    /// state and accepts it with probability `min(1, exp(-ΔE / k_B·T))`.
+    fn update_cell(&mut self, cell_id: usize) {
+        let Some(state) = self.cell_states[cell_id] else {
+            return;
+        };
+        let axes = self.polarizations.current(cell_id).len();
+
+        let mut field = DVector::<f64>::zeros(axes);
+        for neighbour in &self.neighborhood[cell_id] {
+            field += &neighbour.kink_energy
+                * DVector::from_column_slice(self.polarizations.current(neighbour.cell_id));
+        }
+        let clock_energy = self
+            .clock_states
+            .value(self.cells[cell_id].clock_phase_shift)
+            .abs();
+
+        // Uniformly one of the 2·axes other states (polarized or null)
+        let proposal = (state + self.rng.gen_range(1..=2 * axes)) % (2 * axes + 1);
+        let energy_change = state_energy(proposal, &field, clock_energy)
+            - state_energy(state, &field, clock_energy);
+        let thermal_energy = K_B * self.model_settings.temperature;
+        let accepted = energy_change <= 0.0
+            || (thermal_energy > 0.0
+                && self.rng.gen::<f64>() < (-energy_change / thermal_energy).exp());
+
+        if accepted {
+            self.cell_states[cell_id] = Some(proposal);
+            self.polarizations
+                .set_current(cell_id, state_polarization(proposal, axes).as_slice());
+        }
+    }
+}
+
+impl Default for MonteCarloModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulationModelTrait for MonteCarloModel {
+    fn get_name(&self) -> String {
+        "Monte Carlo".into()
+    }
+
+    fn get_unique_id(&self) -> String {
+        "monte_carlo".into()
+    }
+
+    fn get_model_settings(&self) -> Box<dyn SimulationModelSettingsTrait> {
+        Box::new(self.model_settings.clone()) as Box<dyn SimulationModelSettingsTrait>
+    }
+
+    fn get_clock_generator_settings(&self) -> Box<dyn ClockGeneratorSettingsTrait> {
+        Box::new(self.clock_generator_settings.clone()) as Box<dyn ClockGeneratorSettingsTrait>
+    }
+
+    fn get_model_options_list(&self) -> OptionsList {
+        vec![
+            OptionsEntry::Input {
+                unique_id: "temperature".into(),
+                name: "Temperature".into(),
+                description: "Temperature of the Metropolis acceptance".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("K".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "sweeps_per_sample".into(),
+                name: "Sweeps per sample".into(),
+                description: "Monte Carlo sweeps over all cells in every sample".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(1.0),
+                    max: None,
+                    unit: None,
+                    whole_num: true,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "seed".into(),
+                name: "Random seed".into(),
+                description: "Seed of the random number generator".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: None,
+                    whole_num: true,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "neighborhood_radius".into(),
+                name: "Radius of effect".into(),
+                description: "Radius of effect for neighbouring cells".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("nm".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "relative_permitivity".into(),
+                name: "Relative permittivity".into(),
+                description: "Relative permittivity of the medium".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: None,
+                    whole_num: false,
+                },
+            },
+        ]
+    }
+
+    fn get_clock_generator_options_list(&self) -> OptionsList {
+        clock_generator_options(true)
+    }
+
+    fn serialize_model_settings(&self) -> Result<String, String> {
+        serde_json::to_string(&self.model_settings).map_err(|err| err.to_string())
+    }
+
+    fn deserialize_model_settings(&mut self, settings_str: &String) -> Result<(), String> {
+        self.model_settings = serde_json::from_str::<MonteCarloModelSettings>(settings_str)
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn serialize_clock_generator_settings(&self) -> Result<String, String> {
+        serde_json::to_string(&self.clock_generator_settings).map_err(|err| err.to_string())
+    }
+
+    fn deserialize_clock_generator_settings(
+        &mut self,
+        settings_str: &String,
+    ) -> Result<(), String> {
+        self.clock_generator_settings =
+            ClockGeneratorSettings::from_json(settings_str, &ClockGeneratorSettings::default())?;
+        Ok(())
+    }
+
+    fn initiate(
+        &mut self,
+        layers: Box<Vec<QCALayer>>,
+        qca_architetures_map: HashMap<String, QCACellArchitecture>,
+    ) {
+        self.rng = SmallRng::seed_from_u64(self.model_settings.seed);
+        self.cell_ids = CellIds::new(&layers);
+        self.cells = layers
+            .iter()
+            .flat_map(|layer| layer.cells.iter().cloned())
+            .collect();
+        self.polarizations = CellStates::new(
+            &self
+                .cells
+                .iter()
+                .map(|cell| {
+                    dot_probability_distribution_to_polarization(&cell.dot_probability_distribution)
+                })
+                .collect::<Vec<_>>(),
+        );
+        // Dynamic cells start in the null state
+        self.cell_states = self
+            .cells
+            .iter()
+            .enumerate()
+            .map(|(cell_id, cell)| match cell.typ {
+                CellType::Normal | CellType::Output => {
+                    Some(2 * self.polarizations.current(cell_id).len())
+                }
+                CellType::Input | CellType::Fixed => None,
+            })
+            .collect();
+        for (cell_id, state) in self.cell_states.iter().enumerate() {
+            if let Some(state) = state {
+                self.polarizations.set_fixed(cell_id, &vec![0.0; state / 2]);
+            }
+        }
+        self.average_polarizations = CellStates::new(
+            &(0..self.cells.len())
+                .map(|cell_id| self.polarizations.current(cell_id).to_vec())
+                .collect::<Vec<_>>(),
+        );
+        self.error_probabilities = vec![0.0; self.cells.len()];
+        self.input_cells = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.typ == CellType::Input)
+            .enumerate()
+            .map(|(input_index, (cell_id, _))| (cell_id, input_index))
+            .collect();
+
+        self.neighborhood = kink_energy_neighborhood(
+            &layers,
+            &qca_architetures_map,
+            &self.cell_ids,
+            Some(self.model_settings.neighborhood_radius),
+            self.model_settings.relative_permitivity,
+        );
+
+        self.ground_state = IsingModel::new();
+        self.ground_state
+            .deserialize_model_settings(&self.model_settings.ising_settings())
+            .unwrap();
+        self.ground_state.initiate(layers, qca_architetures_map);
+    }
+
+    fn pre_calculate(&mut self, clock_states: &ClockStates, input_states: &Vec<f64>) {
+        self.clock_states = clock_states.clone();
+
+        for &(cell_id, input_index) in self.input_cells.iter() {
+            let polarization_count = self.polarizations.current(cell_id).len();
+            let input_state = &input_states[(polarization_count * input_index)
+                ..(polarization_count * input_index + polarization_count)];
+            self.polarizations.set_fixed(cell_id, input_state);
+            self.average_polarizations.set_fixed(cell_id, input_state);
+        }
+        self.ground_state.pre_calculate(clock_states, input_states);
+    }
+
+    /// Runs the sweeps of the sample on a single cell with all other cells
+    /// held fixed.
+    fn calculate(&mut self, cell_ind: QCACellIndex) -> f64 {
+        self.calculate_all(&[cell_ind], &WorkerPool::default())[0]
+    }
+
+    /// Runs the sweeps of the sample over all cells, one after another, and
+    /// returns the change of their average polarization to the previous
+    /// sample.
+    fn calculate_all(&mut self, cell_indices: &[QCACellIndex], _workers: &WorkerPool) -> Vec<f64> {
+        let cell_ids: Vec<usize> = cell_indices
+            .iter()
+            .map(|cell_ind| self.cell_ids.id(cell_ind))
+            .collect();
+        let sweeps = self.model_settings.sweeps_per_sample.max(1);
+
+        // The Ising model only solves again when the inputs changed
+        self.ground_state
+            .calculate_all(cell_indices, &WorkerPool::default());
+        let references: Vec<Vec<f64>> = cell_indices
+            .iter()
+            .map(|cell_ind| {
+                dot_probability_distribution_to_polarization(
+                    &self.ground_state.get_states(cell_ind),
+                )
+            })
+            .collect();
+
+        let mut sums: Vec<DVector<f64>> = cell_ids
+            .iter()
+            .map(|cell_id| DVector::zeros(self.polarizations.current(*cell_id).len()))
+            .collect();
+        let mut wrong_sweeps = vec![0usize; cell_ids.len()];
+        for _ in 0..sweeps {
+            for (i, cell_id) in cell_ids.iter().enumerate() {
+                self.update_cell(*cell_id);
+                let polarization = self.polarizations.current(*cell_id);
+                if is_wrong(polarization, &references[i]) {
+                    wrong_sweeps[i] += 1;
+                }
+                sums[i] += DVector::from_column_slice(polarization);
+            }
+        }
+        for (cell_id, wrong_sweeps) in cell_ids.iter().zip(wrong_sweeps) {
+            self.error_probabilities[*cell_id] = wrong_sweeps as f64 / sweeps as f64;
+        }
+
+        self.average_polarizations.advance();
+        cell_ids
+            .iter()
+            .zip(sums)
+            .map(|(cell_id, sum)| {
+                let average = sum / sweeps as f64;
+                self.average_polarizations
+                    .set_current(*cell_id, average.as_slice());
+                average
+                    .iter()
+                    .zip(self.average_polarizations.previous(*cell_id))
+                    .map(|(new, old)| (new - old).abs())
+                    .fold(0.0, f64::max)
+            })
+            .collect()
+    }
+
+    fn get_states(&self, cell_ind: &QCACellIndex) -> Vec<f64> {
+        polarization_to_dot_probability_distribution(
+            self.average_polarizations
+                .current(self.cell_ids.id(cell_ind)),
+        )
+    }
+
+    fn get_error_probability(&self, cell_ind: &QCACellIndex) -> Option<f64> {
+        let cell_id = self.cell_ids.id(cell_ind);
+        self.cell_states[cell_id].map(|_| self.error_probabilities[cell_id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::file::QCASimulationData;
+    use crate::simulation::test_designs::wire;
+    use crate::simulation::{run_simulation, SimulationOptions};
+
+    fn simulate_wire(model_settings: &str, clock_generator_settings: &str) -> QCASimulationData {
+        let (layers, architectures) = wire(5);
+        let mut model = MonteCarloModel::new();
+        model
+            .deserialize_model_settings(&model_settings.to_string())
+            .unwrap();
+        model
+            .deserialize_clock_generator_settings(&clock_generator_settings.to_string())
+            .unwrap();
+        run_simulation(
+            Box::new(model),
+            layers,
+            architectures,
+            SimulationOptions::default(),
+        )
+    }
+
+    fn mean_error_probability(simulation: &QCASimulationData) -> f64 {
+        let data = &simulation.error_probabilities[0].data;
+        data.iter().sum::<f64>() / data.len() as f64
+    }
+
+    #[test]
+    fn test_state_energy() {
+        let field = DVector::from_vec(vec![0.4, -0.2]);
+        assert_eq!(state_energy(4, &field, 1.0), 0.0);
+        assert!((state_energy(0, &field, 0.1) - (0.1 - 0.2)).abs() < 1e-12);
+        assert!((state_energy(3, &field, 0.1) - (0.1 - 0.1)).abs() < 1e-12);
+        assert_eq!(state_polarization(2, 2).as_slice(), &[-1.0, 0.0]);
+    }
+
+    #[test]
+    fn test_samples_skip_convergence_check() {
+        let (layers, architectures) = wire(4);
+        let simulation = run_simulation(
+            Box::new(MonteCarloModel::new()),
+            layers,
+            architectures,
+            SimulationOptions::default(),
+        );
+        assert_eq!(simulation.metadata.num_samples, 40);
+        assert!(simulation.convergence_data.is_empty());
+    }
+
+    #[test]
+    fn test_is_wrong() {
+        assert!(!is_wrong(&[0.0, 0.0], &[1.0, 0.0]));
+        assert!(!is_wrong(&[1.0, 0.0], &[1.0, 0.0]));
+        assert!(is_wrong(&[-1.0, 0.0], &[1.0, 0.0]));
+        assert!(is_wrong(&[0.0, 1.0], &[1.0, 0.0]));
+        assert!(!is_wrong(&[-1.0], &[0.0]));
+    }
+
+    #[test]
+    fn test_seed_reproduces_samples() {
+        let first = simulate_wire(r#"{"temperature": 5.0, "seed": 1}"#, "{}");
+        let second = simulate_wire(r#"{"temperature": 5.0, "seed": 1}"#, "{}");
+        let other = simulate_wire(r#"{"temperature": 5.0, "seed": 2}"#, "{}");
+
+        assert_eq!(first.cells_data[1].data, second.cells_data[1].data);
+        assert_eq!(
+            first.error_probabilities[0].data,
+            second.error_probabilities[0].data
+        );
+        assert_ne!(first.cells_data[1].data, other.cells_data[1].data);
+        assert_ne!(
+            first.error_probabilities[0].data,
+            other.error_probabilities[0].data
+        );
+    }
+
+    #[test]
+    fn test_temperature_raises_error_probability() {
+        let cold = simulate_wire(r#"{"temperature": 1.0}"#, "{}");
+        let hot = simulate_wire(r#"{"temperature": 5.0}"#, "{}");
+        assert_eq!(cold.error_probabilities.len(), 1);
+        assert!(mean_error_probability(&cold) < 0.05);
+        assert!(mean_error_probability(&hot) > 2.0 * mean_error_probability(&cold));
+    }
+
+    /// The 4-dot wire inverts at every cell, so the output at the fifth cell
+    /// follows the input. A clock dropping within a few samples freezes a
+    /// wrong state at zero temperature, a slow one reaches the ground state.
+    #[test]
+    fn test_slow_clock_reaches_ground_state() {
+        let fast = simulate_wire(r#"{"temperature": 0.0}"#, "{}");
+        assert_eq!(
+            fast.error_probabilities[0]
+                .data
+                .iter()
+                .cloned()
+                .fold(0.0, f64::max),
+            1.0
+        );
+
+        let slow = simulate_wire(r#"{"temperature": 0.0}"#, r#"{"samples_per_input": 200}"#);
+        assert_eq!(mean_error_probability(&slow), 0.0);
+        let polarized: Vec<(f64, f64)> = slow.cells_data[0]
+            .data
+            .iter()
+            .zip(&slow.cells_data[1].data)
+            .filter(|(_, output)| output.abs() > 0.5)
+            .map(|(input, output)| (*input, *output))
+            .collect();
+        assert!(!polarized.is_empty());
+        for (input, output) in polarized {
+            assert_eq!(input.signum(), output.signum());
+        }
+    }
+}
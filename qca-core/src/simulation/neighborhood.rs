@@ -0,0 +1,142 @@
+use crate::objects::cell::QCACellIndex;
+use crate::objects::layer::QCALayer;
+use std::collections::HashMap;
+
+type Bucket = Vec<(QCACellIndex, [f64; 2])>;
+
+/// Uniform grid over the cell positions of a design, used to find all cells
+/// within a cutoff radius without comparing every pair of cells.
+///
+/// Distances are measured in the layer plane, so cells on different layers
+/// at the same position are neighbours of each other.
+pub struct NeighborhoodIndex {
+    radius: Option<f64>,
+    buckets: HashMap<(i64, i64), Bucket>,
+}
+
+impl NeighborhoodIndex {
+    /// Build the index for all cells of the given layers. A radius of `None`
+    /// disables the cutoff and every cell becomes a neighbour of every other.
+    pub fn new(layers: &[QCALayer], radius: Option<f64>) -> Self {
+        let mut index = NeighborhoodIndex {
+            radius,
+            buckets: HashMap::new(),
+        };
+
+        layers.iter().enumerate().for_each(|(i, layer)| {
+            layer.cells.iter().enumerate().for_each(|(j, cell)| {
+                index
+                    .buckets
+                    .entry(index.bucket(cell.position))
+                    .or_default()
+                    .push((QCACellIndex::new(i, j), cell.position));
+            })
+        });
+
+        index
+    }
+
+    fn bucket(&self, position: [f64; 2]) -> (i64, i64) {
+        match self.radius {
+            Some(radius) if radius > 0.0 => (
+                (position[0] / radius).floor() as i64,
+                (position[1] / radius).floor() as i64,
+            ),
+            _ => (0, 0),
+        }
+    }
+
+    /// All cells within the cutoff radius of the given cell, excluding the
+    /// cell itself, ordered by their index.
+    pub fn neighbors(&self, cell_index: &QCACellIndex, position: [f64; 2]) -> Vec<QCACellIndex> {
+        let (bx, by) = self.bucket(position);
+        let span = if self.radius.is_some() { 1 } else { 0 };
+
+        let mut neighbors: Vec<QCACellIndex> = (bx - span..=bx + span)
+            .flat_map(|x| (by - span..=by + span).map(move |y| (x, y)))
+            .filter_map(|key| self.buckets.get(&key))
+            .flatten()
+            .filter(|(index, other_position)| {
+                index != cell_index
+                    && self.radius.is_none_or(|radius| {
+                        f64::hypot(
+                            position[0] - other_position[0],
+                            position[1] - other_position[1],
+                        ) <= radius
+                    })
+            })
+            .map(|(index, _)| index.clone())
+            .collect();
+
+        neighbors.sort();
+        neighbors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::cell::{CellType, QCACell};
+
+    fn grid_layers() -> Vec<QCALayer> {
+        (0..2)
+            .map(|l| {
+                let mut layer = QCALayer::new(format!("{}", l), "arch".into(), l as f64 * 10.0);
+                layer.cells = (0..100)
+                    .map(|i| QCACell {
+                        position: [(i % 10) as f64 * 20.0 - 90.0, (i / 10) as f64 * 20.0 + 5.0],
+                        rotation: 0.0,
+                        typ: CellType::Normal,
+                        clock_phase_shift: 0.0,
+                        dot_probability_distribution: vec![0.5; 4],
+                        label: None,
+                    })
+                    .collect();
+                layer
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_neighbors_match_exhaustive_search() {
+        let layers = grid_layers();
+        for radius in [0.0, 20.0, 30.0, 65.0] {
+            let index = NeighborhoodIndex::new(&layers, Some(radius));
+            for (i, layer) in layers.iter().enumerate() {
+                for (j, cell) in layer.cells.iter().enumerate() {
+                    let cell_index = QCACellIndex::new(i, j);
+                    let expected: Vec<QCACellIndex> = layers
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(k, other_layer)| {
+                            other_layer
+                                .cells
+                                .iter()
+                                .enumerate()
+                                .filter(|(_, other)| {
+                                    f64::hypot(
+                                        cell.position[0] - other.position[0],
+                                        cell.position[1] - other.position[1],
+                                    ) <= radius
+                                })
+                                .map(move |(l, _)| QCACellIndex::new(k, l))
+                        })
+                        .filter(|other_index| *other_index != cell_index)
+                        .collect();
+
+                    assert_eq!(index.neighbors(&cell_index, cell.position), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_neighbors_without_cutoff() {
+        let layers = grid_layers();
+        let index = NeighborhoodIndex::new(&layers, None);
+        let neighbors = index.neighbors(&QCACellIndex::new(0, 0), layers[0].cells[0].position);
+
+        assert_eq!(neighbors.len(), 199);
+        assert!(!neighbors.contains(&QCACellIndex::new(0, 0)));
+    }
+}
@@ -0,0 +1,400 @@
+use super::{QCACellArchitecture, SimulationModelTrait};
+use crate::objects::cell::QCACellIndex;
+use crate::objects::layer::QCALayer;
+use crate::simulation::bistable::BistableModel;
+use crate::simulation::clock_generator::{ClockShape, ClockStates};
+use crate::simulation::model::{
+    clock_generator_options, ClockGeneratorSettings, ClockGeneratorSettingsTrait,
+    SimulationModelSettingsTrait, UpdateScheme, WorkerPool,
+};
+use crate::simulation::settings::{InputDescriptor, OptionsEntry, OptionsList};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use std::collections::HashMap;
+
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QCADesignerModelSettings {
+    #[serde_inline_default(100)]
+    max_iterations: usize,
+
+    #[serde_inline_default(1e-3)]
+    convergence_tolerance: f64,
+
+    #[serde_inline_default(65.0)]
+    neighborhood_radius: f64,
+
+    #[serde_inline_default(12.9)]
+    relative_permitivity: f64,
+
+    #[serde_inline_default(true)]
+    randomize_cells: bool,
+
+    #[serde_inline_default(0)]
+    seed: u64,
+}
+
+/// Defaults of QCADesigner's bistable engine: a clock between 3.8e-23 J and
+/// 9.8e-22 J (in meV) and two clock cycles per input vector.
+impl QCADesignerModelSettings {
+    pub fn new() -> Self {
+        serde_json::from_str::<QCADesignerModelSettings>("{}").unwrap()
+    }
+
+    /// Settings of the bistable model performing the cell updates.
+    fn bistable_settings(&self) -> String {
+        serde_json::json!({
+            "max_iterations": self.max_iterations,
+            "convergence_tolerance": self.convergence_tolerance,
+            "update_scheme": UpdateScheme::GaussSeidel,
+            "neighborhood_radius": self.neighborhood_radius,
+            "relative_permitivity": self.relative_permitivity,
+            "temperature": 0.0,
+        })
+        .to_string()
+    }
+}
+
+impl Default for QCADesignerModelSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cells are updated in place, one after another, as in QCADesigner.
+impl SimulationModelSettingsTrait for QCADesignerModelSettings {
+    fn get_max_iterations(&self) -> usize {
+        self.max_iterations
+    }
+    fn get_convergence_tolerance(&self) -> f64 {
+        self.convergence_tolerance
+    }
+    fn get_update_scheme(&self) -> UpdateScheme {
+        UpdateScheme::GaussSeidel
+    }
+    fn get_damping_factor(&self) -> f64 {
+        1.0
+    }
+}
+
+/// QCADesigner's nonlinear approximation ("bistable") engine. The kink
+/// energies and the saturating cell response are those of the bistable
+/// model, which follows the same formulation. On top of that every iteration
+/// updates the cells in place in a freshly shuffled order, and the defaults
+/// match QCADesigner's, so results can be compared directly with published
+/// QCADesigner simulations of the same layout. The shuffle is seeded by the
+/// `seed` setting, so runs are reproducible.
+pub struct QCADesignerModel {
+    model_settings: QCADesignerModelSettings,
+    clock_generator_settings: ClockGeneratorSettings,
+    bistable: BistableModel,
+    rng: SmallRng,
+}
+
+impl QCADesignerModel {
+    /// Clock generator defaults of QCADesigner's bistable engine: two cycles
+    /// of a sinusoidal clock sampled 3200 times per input.
+    const CLOCK_GENERATOR_DEFAULTS: ClockGeneratorSettings = ClockGeneratorSettings {
+        num_cycles: 2,
+        amplitude_min: 0.237_177,
+        amplitude_max: 6.116_679,
+        samples_per_input: 3200,
+        clock_shape: ClockShape::Sinusoidal,
+        amplitude_factor: 2.0,
+        ..ClockGeneratorSettings::DEFAULT
+    };
+
+    pub fn new() -> Self {
+        QCADesignerModel {
+            model_settings: QCADesignerModelSettings::new(),
+            clock_generator_settings: Self::CLOCK_GENERATOR_DEFAULTS,
+            bistable: BistableModel::new(),
+            rng: SmallRng::seed_from_u64(0),
+        }
+    }
+}
+
+impl Default for QCADesignerModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulationModelTrait for QCADesignerModel {
+    fn get_name(&self) -> String {
+        "QCADesigner Nonlinear Approximation".into()
+    }
+
+    fn get_unique_id(&self) -> String {
+        "qcadesigner".into()
+    }
+
+    fn get_model_settings(&self) -> Box<dyn SimulationModelSettingsTrait> {
+        Box::new(self.model_settings.clone()) as Box<dyn SimulationModelSettingsTrait>
+    }
+
+    fn get_clock_generator_settings(&self) -> Box<dyn ClockGeneratorSettingsTrait> {
+        Box::new(self.clock_generator_settings.clone()) as Box<dyn ClockGeneratorSettingsTrait>
+    }
+
+    fn get_model_options_list(&self) -> OptionsList {
+        vec![
+            OptionsEntry::Input {
+                unique_id: "max_iterations".into(),
+                name: "Maximum iterations per sample".into(),
+                description:
+                    "The maximum number of iterations used for simulation convergence check".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(1.0),
+                    max: None,
+                    unit: None,
+                    whole_num: true,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "convergence_tolerance".into(),
+                name: "Convergence tolerance".into(),
+                description: "Tolerance for simulation convergence check".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: Some(1.0),
+                    unit: None,
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "neighborhood_radius".into(),
+                name: "Radius of effect".into(),
+                description: "Radius of effect for neighbouring cells".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: Some("nm".into()),
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "relative_permitivity".into(),
+                name: "Relative permittivity".into(),
+                description: "Relative permittivity of the medium".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: None,
+                    whole_num: false,
+                },
+            },
+            OptionsEntry::Input {
+                unique_id: "randomize_cells".into(),
+                name: "Randomize simulation order".into(),
+                description: "Update the cells in a random order in every iteration".into(),
+                descriptor: InputDescriptor::BoolInput {},
+            },
+            OptionsEntry::Input {
+                unique_id: "seed".into(),
+                name: "Random seed".into(),
+                description: "Seed of the random simulation order".into(),
+                descriptor: InputDescriptor::NumberInput {
+                    min: Some(0.0),
+                    max: None,
+                    unit: None,
+                    whole_num: true,
+                },
+            },
+        ]
+    }
+
+    fn get_clock_generator_options_list(&self) -> OptionsList {
+        clock_generator_options(true)
+    }
+
+    fn serialize_model_settings(&self) -> Result<String, String> {
+        serde_json::to_string(&self.model_settings).map_err(|err| err.to_string())
+    }
+
+    fn deserialize_model_settings(&mut self, settings_str: &String) -> Result<(), String> {
+        self.model_settings = serde_json::from_str::<QCADesignerModelSettings>(settings_str)
+            .map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn serialize_clock_generator_settings(&self) -> Result<String, String> {
+        serde_json::to_string(&self.clock_generator_settings).map_err(|err| err.to_string())
+    }
+
+    fn deserialize_clock_generator_settings(
+        &mut self,
+        settings_str: &String,
+    ) -> Result<(), String> {
+        self.clock_generator_settings =
+            ClockGeneratorSettings::from_json(settings_str, &Self::CLOCK_GENERATOR_DEFAULTS)?;
+        Ok(())
+    }
+
+    fn initiate(
+        &mut self,
+        layers: Box<Vec<QCALayer>>,
+        qca_architetures_map: HashMap<String, QCACellArchitecture>,
+    ) {
+        self.rng = SmallRng::seed_from_u64(self.model_settings.seed);
+        self.bistable
+            .deserialize_model_settings(&self.model_settings.bistable_settings())
+            .unwrap();
+        self.bistable.initiate(layers, qca_architetures_map);
+    }
+
+    fn pre_calculate(&mut self, clock_states: &ClockStates, input_states: &Vec<f64>) {
+        self.bistable.pre_calculate(clock_states, input_states);
+    }
+
+    fn calculate(&mut self, cell_ind: QCACellIndex) -> f64 {
+        self.bistable.calculate(cell_ind)
+    }
+
+    /// Updates the cells one after another, in a shuffled order if cells are
+    /// randomized, and returns the residuals in the given order.
+    fn calculate_all(&mut self, cell_indices: &[QCACellIndex], _workers: &WorkerPool) -> Vec<f64> {
+        let mut order: Vec<usize> = (0..cell_indices.len()).collect();
+        if self.model_settings.randomize_cells {
+            order.shuffle(&mut self.rng);
+        }
+
+        let mut residuals = vec![0.0; cell_indices.len()];
+        for i in order {
+            residuals[i] = self.bistable.calculate(cell_indices[i].clone());
+        }
+        residuals
+    }
+
+    fn get_states(&self, cell_ind: &QCACellIndex) -> Vec<f64> {
+        self.bistable.get_states(cell_ind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::cell::{dot_probability_distribution_to_polarization, CellType, QCACell};
+    use crate::simulation::test_designs::wire;
+    use crate::simulation::{run_simulation, SimulationOptions};
+
+    fn relax(model: &mut dyn SimulationModelTrait, cell_indices: &[QCACellIndex], input: f64) {
+        for clock in [6.116_679, 0.237_177] {
+            let clock_states = ClockStates::zones(vec![-clock; 4]);
+            for _ in 0..100 {
+                model.pre_calculate(&clock_states, &vec![input]);
+                let residuals = model.calculate_all(cell_indices, &WorkerPool::default());
+                if residuals.into_iter().fold(0.0, f64::max) <= 1e-6 {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_randomized_order_reaches_bistable_state() {
+        let (layers, architectures) = wire(6);
+        let cell_indices: Vec<QCACellIndex> = (0..layers[0].cells.len())
+            .map(|c| QCACellIndex::new(0, c))
+            .collect();
+        let mut model = QCADesignerModel::new();
+        model.initiate(Box::new(layers.clone()), architectures.clone());
+        let mut bistable = BistableModel::new();
+        bistable
+            .deserialize_model_settings(&model.model_settings.bistable_settings())
+            .unwrap();
+        bistable.initiate(Box::new(layers), architectures);
+
+        for input in [1.0, -1.0] {
+            relax(&mut model, &cell_indices, input);
+            relax(&mut bistable, &cell_indices, input);
+            for cell_index in &cell_indices {
+                let polarization =
+                    dot_probability_distribution_to_polarization(&model.get_states(cell_index));
+                let expected =
+                    dot_probability_distribution_to_polarization(&bistable.get_states(cell_index));
+                assert!(polarization[0].abs() > 0.5);
+                assert!((polarization[0] - expected[0]).abs() < 1e-4);
+            }
+        }
+    }
+
+    /// QCADesigner's standard majority gate: inputs A, B and C, above, left
+    /// of and below the device cell, and the output two cells to its right,
+    /// all in one clock zone. QCADesigner's cells carry their four dots in
+    /// the corners, 4.5 nm from the centre along both axes.
+    fn majority_gate() -> (Vec<QCALayer>, HashMap<String, QCACellArchitecture>) {
+        let architecture = QCACellArchitecture::new(18.0, 5.0, 4, 4.5 * std::f64::consts::SQRT_2);
+        let mut layer = QCALayer::new("Main Layer".into(), "qcadesigner".into(), 0.0);
+        let cells = [
+            ([0.0, -40.0], CellType::Input),
+            ([-40.0, 0.0], CellType::Input),
+            ([0.0, 40.0], CellType::Input),
+            ([0.0, -20.0], CellType::Normal),
+            ([-20.0, 0.0], CellType::Normal),
+            ([0.0, 20.0], CellType::Normal),
+            ([0.0, 0.0], CellType::Normal),
+            ([20.0, 0.0], CellType::Normal),
+            ([40.0, 0.0], CellType::Output),
+        ];
+        layer.cells = cells
+            .into_iter()
+            .map(|(position, typ)| QCACell {
+                position,
+                rotation: std::f64::consts::FRAC_PI_4,
+                typ,
+                clock_phase_shift: 0.0,
+                dot_probability_distribution: vec![0.5; 4],
+                label: None,
+            })
+            .collect();
+
+        (
+            vec![layer],
+            HashMap::from([("qcadesigner".to_string(), architecture)]),
+        )
+    }
+
+    /// With the clock low QCADesigner's bistable engine drives the output of
+    /// its majority gate to the majority of the inputs, saturating at about
+    /// ±0.95, whatever order the cells are updated in.
+    #[test]
+    fn test_majority_gate_matches_qcadesigner() {
+        for seed in [0, 7] {
+            let (layers, architectures) = majority_gate();
+            let mut model = QCADesignerModel::new();
+            model
+                .deserialize_model_settings(&format!(r#"{{"seed": {}}}"#, seed))
+                .unwrap();
+            model
+                .deserialize_clock_generator_settings(&r#"{"samples_per_input": 1600}"#.into())
+                .unwrap();
+            let simulation = run_simulation(
+                Box::new(model),
+                layers,
+                architectures,
+                SimulationOptions::default(),
+            );
+            assert_eq!(simulation.metadata.num_samples, 8 * 1600);
+
+            let mut held_samples = 0;
+            for sample in 0..simulation.metadata.num_samples {
+                if simulation.clock_data[0][sample].abs() > 0.3 {
+                    continue;
+                }
+                let input_sum: f64 = simulation.cells_data[..3]
+                    .iter()
+                    .map(|input| input.data[sample])
+                    .sum();
+                let output = simulation.cells_data[3].data[sample];
+                assert_eq!(output.signum(), input_sum.signum());
+                assert!(output.abs() > 0.9);
+                held_samples += 1;
+            }
+            assert!(held_samples > 0);
+        }
+    }
+}
@@ -39,4 +39,13 @@ pub enum InputDescriptor {
     },
     StringInput {},
     BoolInput {},
+    SelectInput {
+        options: Vec<SelectOption>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SelectOption {
+    pub value: String,
+    pub label: String,
 }
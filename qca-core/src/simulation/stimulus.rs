@@ -0,0 +1,337 @@
+use crate::objects::bus::Bus;
+use crate::objects::cell::{CellType, QCACellIndex};
+use crate::objects::generator::{Generator, GeneratorConfig};
+use crate::objects::layer::QCALayer;
+use crate::simulation::input_generator::InputWaveform;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_inline_default::serde_inline_default;
+use std::collections::BTreeMap;
+
+/// Value of an input while it is held.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum StimulusValue {
+    /// Polarization along the first polarization axis
+    Polarization(f64),
+    /// Polarization per polarization axis
+    Axes(Vec<f64>),
+}
+
+impl StimulusValue {
+    /// Polarization per axis, missing axes are unpolarized.
+    fn axes(&self, num_polarization: usize) -> Vec<f64> {
+        let mut axes = match self {
+            StimulusValue::Polarization(value) => vec![*value],
+            StimulusValue::Axes(values) => values.clone(),
+        };
+        axes.resize(num_polarization, 0.0);
+        axes
+    }
+}
+
+/// Step of the stimulus of an input, or of a bus with integer values.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum StimulusStep<T = StimulusValue> {
+    /// Holds a value for a number of clock cycles
+    Hold { value: T, hold: usize },
+    /// Repeats a block of steps
+    Repeat {
+        repeat: usize,
+        steps: Vec<StimulusStep<T>>,
+    },
+}
+
+impl<T: Clone> StimulusStep<T> {
+    /// Appends the value of every clock cycle of the step.
+    fn expand(&self, cycles: &mut Vec<T>) {
+        match self {
+            StimulusStep::Hold { value, hold } => {
+                cycles.extend((0..*hold).map(|_| value.clone()));
+            }
+            StimulusStep::Repeat { repeat, steps } => {
+                for _ in 0..*repeat {
+                    for step in steps {
+                        step.expand(cycles);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sequence of input values per input label, used instead of enumerating all
+/// input combinations to drive sequential circuits.
+///
+/// Inputs are matched by their label, unlabelled inputs by their cell index
+/// (`layer-cell`). Buses drive their cells with the bits of integer values,
+/// inputs listed by label take precedence. Inputs without steps, and inputs
+/// whose steps end before the others, are unpolarized.
+#[serde_inline_default]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Stimulus {
+    #[serde_inline_default(BTreeMap::new())]
+    pub inputs: BTreeMap<String, Vec<StimulusStep>>,
+    #[serde_inline_default(BTreeMap::new())]
+    pub buses: BTreeMap<Bus, Vec<StimulusStep<u64>>>,
+}
+
+impl Stimulus {
+    /// Parses a stimulus script, rejecting bus values wider than their bus.
+    pub fn from_json(contents: &str) -> Result<Self, String> {
+        let stimulus: Stimulus =
+            serde_json::from_str(contents).map_err(|e| format!("Invalid stimulus: {}", e))?;
+        for (bus, steps) in &stimulus.buses {
+            let mut values = Vec::new();
+            steps.iter().for_each(|step| step.expand(&mut values));
+            if let Some(value) = values.into_iter().find(|value| !bus.fits(*value)) {
+                return Err(format!(
+                    "Invalid stimulus: value {} does not fit in the {} bits of bus {}",
+                    value,
+                    bus.width(),
+                    bus
+                ));
+            }
+        }
+        Ok(stimulus)
+    }
+
+    /// Labels of the stimulus, including the cells of its buses, that match
+    /// no input cell of the design.
+    pub fn unknown_labels(&self, layers: &[QCALayer]) -> Vec<String> {
+        let input_labels = get_input_labels(layers);
+        self.inputs
+            .keys()
+            .cloned()
+            .chain(self.buses.keys().flat_map(|bus| bus.bit_labels()))
+            .filter(|label| !input_labels.contains(label))
+            .collect()
+    }
+
+    /// Values per clock cycle and polarization axis of every input cell, in
+    /// the order of the cells in the layers.
+    pub fn input_cycles(&self, layers: &[QCALayer], num_polarization: usize) -> Vec<Vec<Vec<f64>>> {
+        get_input_labels(layers)
+            .into_iter()
+            .map(|label| {
+                if let Some(steps) = self.inputs.get(&label) {
+                    let mut values = Vec::new();
+                    steps.iter().for_each(|step| step.expand(&mut values));
+                    return values
+                        .iter()
+                        .map(|value| value.axes(num_polarization))
+                        .collect();
+                }
+                for (bus, steps) in &self.buses {
+                    if !bus.bit_labels().contains(&label) {
+                        continue;
+                    }
+                    let mut values = Vec::new();
+                    steps.iter().for_each(|step| step.expand(&mut values));
+                    return values
+                        .iter()
+                        .map(|value| {
+                            let polarization = if bus.bit(&label, *value) == Some(true) {
+                                1.0
+                            } else {
+                                -1.0
+                            };
+                            StimulusValue::Polarization(polarization).axes(num_polarization)
+                        })
+                        .collect();
+                }
+                warn!("No stimulus for input {}, it is unpolarized", label);
+                Vec::new()
+            })
+            .collect()
+    }
+}
+
+/// Label of every input cell, or its cell index if it has none.
+fn get_input_labels(layers: &[QCALayer]) -> Vec<String> {
+    layers
+        .iter()
+        .enumerate()
+        .flat_map(|(l, layer)| {
+            layer
+                .cells
+                .iter()
+                .enumerate()
+                .filter(|(_, cell)| cell.typ == CellType::Input)
+                .map(move |(c, cell)| match &cell.label {
+                    Some(label) => label.clone(),
+                    None => QCACellIndex::new(l, c).to_string(),
+                })
+        })
+        .collect()
+}
+
+/// Configuration for the stimulus input generator
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StimulusInputConfig {
+    /// Values per input, clock cycle and polarization axis
+    pub input_cycles: Vec<Vec<Vec<f64>>>,
+    /// Number of samples per clock cycle
+    pub samples_per_cycle: usize,
+    /// Number of polarization states
+    pub num_polarization: usize,
+    /// Number of extra clock periods to generate
+    pub extra_clock_periods: usize,
+    /// Number of samples before the first clock cycle of the stimulus, with
+    /// unpolarized inputs
+    pub offset: usize,
+    /// Shape of the input signals, transitions ramp over a fraction of a
+    /// clock cycle
+    pub waveform: InputWaveform,
+}
+
+impl GeneratorConfig for StimulusInputConfig {}
+
+/// Generator for cell input values that follow a stimulus cycle by cycle
+/// Output dimension: num_inputs * num_polarization
+pub struct StimulusInputGenerator {
+    config: StimulusInputConfig,
+    num_samples: usize,
+}
+
+impl Generator for StimulusInputGenerator {
+    type Config = StimulusInputConfig;
+    type Output = Vec<f64>;
+
+    fn new(config: Self::Config) -> Self {
+        let num_cycles = config
+            .input_cycles
+            .iter()
+            .map(|cycles| cycles.len())
+            .max()
+            .unwrap_or(0);
+        let num_samples =
+            config.offset + (num_cycles + config.extra_clock_periods) * config.samples_per_cycle;
+        Self {
+            config,
+            num_samples,
+        }
+    }
+
+    fn generate(&self, sample: usize) -> Option<Self::Output> {
+        if sample >= self.num_samples {
+            return None;
+        }
+        if sample < self.config.offset {
+            return Some(self.cycle_values(None));
+        }
+        let sample = sample - self.config.offset;
+
+        let cycle = sample / self.config.samples_per_cycle;
+        let progress =
+            (sample % self.config.samples_per_cycle) as f64 / self.config.samples_per_cycle as f64;
+
+        let weight = self.config.waveform.transition_weight(progress);
+        let output = self.cycle_values(Some(cycle));
+        if weight >= 1.0 {
+            return Some(output);
+        }
+
+        let previous = self.cycle_values(cycle.checked_sub(1));
+        Some(
+            previous
+                .into_iter()
+                .zip(output)
+                .map(|(previous, value)| previous + (value - previous) * weight)
+                .collect(),
+        )
+    }
+
+    fn num_samples(&self) -> usize {
+        self.num_samples
+    }
+}
+
+impl StimulusInputGenerator {
+    /// Input values of a clock cycle once its transition is done, unpolarized
+    /// before the first cycle and after the end of an input's stimulus
+    fn cycle_values(&self, cycle: Option<usize>) -> Vec<f64> {
+        self.config
+            .input_cycles
+            .iter()
+            .flat_map(|cycles| match cycle.and_then(|cycle| cycles.get(cycle)) {
+                Some(axes) => axes
+                    .iter()
+                    .map(|value| value * self.config.waveform.magnitude)
+                    .collect(),
+                None => vec![0.0; self.config.num_polarization],
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stimulus_holds_and_repeats() {
+        let stimulus = Stimulus::from_json(
+            r#"{"inputs": {
+                "W": [{"value": 1, "hold": 1}, {"repeat": 2, "steps": [{"value": -1, "hold": 1}]}],
+                "X": [{"value": [0, 1], "hold": 2}]
+            }}"#,
+        )
+        .unwrap();
+        let expected = vec![
+            vec![vec![1.0, 0.0], vec![-1.0, 0.0], vec![-1.0, 0.0]],
+            vec![vec![0.0, 1.0], vec![0.0, 1.0]],
+        ];
+        assert_eq!(
+            stimulus.inputs["W"]
+                .iter()
+                .chain(stimulus.inputs["X"].iter())
+                .fold(Vec::new(), |mut cycles, step| {
+                    step.expand(&mut cycles);
+                    cycles
+                })
+                .iter()
+                .map(|value| value.axes(2))
+                .collect::<Vec<_>>(),
+            expected.concat()
+        );
+
+        let generator = StimulusInputGenerator::new(StimulusInputConfig {
+            input_cycles: expected,
+            samples_per_cycle: 2,
+            num_polarization: 2,
+            extra_clock_periods: 1,
+            offset: 1,
+            waveform: InputWaveform::default(),
+        });
+        assert_eq!(generator.num_samples(), 9);
+        let samples: Vec<Vec<f64>> = generator.iter().collect();
+        assert_eq!(samples[0], vec![0.0; 4]);
+        assert_eq!(samples[1], vec![1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(samples[4], vec![-1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(samples[5], vec![-1.0, 0.0, 0.0, 0.0]);
+        assert_eq!(samples[8], vec![0.0; 4]);
+    }
+
+    #[test]
+    fn test_bus_stimulus() {
+        let stimulus =
+            Stimulus::from_json(r#"{"buses": {"A[0..1]": [{"value": 2, "hold": 3}]}}"#).unwrap();
+        let bus: Bus = "A[0..1]".parse().unwrap();
+        assert!(stimulus.inputs.is_empty());
+        assert_eq!(
+            stimulus.buses[&bus],
+            vec![StimulusStep::Hold { value: 2, hold: 3 }]
+        );
+
+        assert!(
+            Stimulus::from_json(r#"{"buses": {"A[0..1]": [{"value": 3, "hold": 1}]}}"#).is_ok()
+        );
+        let error = Stimulus::from_json(
+            r#"{"buses": {"A[0..1]": [{"repeat": 2, "steps": [{"value": 7, "hold": 1}]}]}}"#,
+        )
+        .unwrap_err();
+        assert!(error.contains("value 7"), "{}", error);
+    }
+}
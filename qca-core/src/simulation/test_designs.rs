@@ -0,0 +1,65 @@
+//! Designs shared by the tests of the simulation models and the analysis.
+
+use crate::objects::architecture::QCACellArchitecture;
+use crate::objects::cell::{CellType, QCACell};
+use crate::objects::layer::QCALayer;
+use std::collections::HashMap;
+
+/// Horizontal wire of 4-dot cells 20 nm apart in row `y`, an input followed
+/// by normal cells and an output.
+pub(crate) fn wire_cells(length: usize, y: f64) -> Vec<QCACell> {
+    (0..length)
+        .map(|i| QCACell {
+            position: [i as f64 * 20.0, y],
+            rotation: 0.0,
+            typ: match i {
+                0 => CellType::Input,
+                i if i == length - 1 => CellType::Output,
+                _ => CellType::Normal,
+            },
+            clock_phase_shift: 0.0,
+            dot_probability_distribution: vec![0.5; 4],
+            label: None,
+        })
+        .collect()
+}
+
+/// Wire of 18 nm 4-dot cells. The dots sit on the diagonals, so the cells
+/// invert along the wire.
+pub(crate) fn wire(length: usize) -> (Vec<QCALayer>, HashMap<String, QCACellArchitecture>) {
+    let architecture = QCACellArchitecture::new(18.0, 5.0, 4, 4.5);
+    let mut layer = QCALayer::new("Main Layer".into(), "two_state".into(), 0.0);
+    layer.cells = wire_cells(length, 0.0);
+
+    (
+        vec![layer],
+        HashMap::from([("two_state".to_string(), architecture)]),
+    )
+}
+
+/// Wire of 60 nm 8-dot cells with two polarization axes.
+pub(crate) fn tri_state_wire(
+    length: usize,
+) -> (Vec<QCALayer>, HashMap<String, QCACellArchitecture>) {
+    let architecture = QCACellArchitecture::new(60.0, 10.0, 8, 17.420839531685022);
+    let mut layer = QCALayer::new("Main Layer".into(), "tri_state_60".into(), 0.0);
+    layer.cells = (0..length)
+        .map(|i| QCACell {
+            position: [i as f64 * 60.0, 0.0],
+            rotation: 0.0,
+            typ: match i {
+                0 => CellType::Input,
+                i if i == length - 1 => CellType::Output,
+                _ => CellType::Normal,
+            },
+            clock_phase_shift: 0.0,
+            dot_probability_distribution: vec![0.25; 8],
+            label: None,
+        })
+        .collect();
+
+    (
+        vec![layer],
+        HashMap::from([("tri_state_60".to_string(), architecture)]),
+    )
+}
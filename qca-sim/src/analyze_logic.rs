@@ -1,6 +1,7 @@
 use clap::builder::PathBufValueParser;
 use clap::{Arg, ArgMatches, Command};
-use qca_core::analysis::truth_table::generate_truth_table;
+use qca_core::analysis::truth_table::{generate_truth_table, TruthTableThresholds};
+use qca_core::objects::bus::Bus;
 use qca_core::objects::cell::QCACellIndex;
 use qca_core::simulation::file::{read_from_file, SIMULATION_FILE_EXTENSION};
 use std::collections::HashMap;
@@ -98,6 +99,21 @@ pub fn get_analyze_logic_subcommand() -> Command {
                 .value_name("<CellIndex|CellLabel>:<ClockDelay>")
                 .action(clap::ArgAction::Append), // Allow multiple values
         )
+        .arg(
+            Arg::new("per-input")
+                .help("Report one value per input period instead of per clock cycle, clock delays then count input periods")
+                .long("per-input")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("bus")
+                .help("Bus of cells labelled <Name><Bit> to decode to integers, buses of the stimulus are decoded by default (format: <Name>[<First>..<Last>])")
+                .long("bus")
+                .short('b')
+                .value_parser(Bus::from_str)
+                .value_name("<Name>[<First>..<Last>]")
+                .action(clap::ArgAction::Append),
+        )
 }
 
 pub fn run_analyze_logic(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
@@ -147,15 +163,45 @@ pub fn run_analyze_logic(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         })
         .unwrap_or_else(|| Ok(HashMap::new()))?;
 
-    let truth_table = generate_truth_table(
+    let mut buses: Vec<Bus> = simulation
+        .metadata
+        .stimulus
+        .iter()
+        .flat_map(|stimulus| stimulus.buses.keys().cloned())
+        .collect();
+    for bus in matches.get_many::<Bus>("bus").into_iter().flatten() {
+        if !buses.contains(bus) {
+            buses.push(bus.clone());
+        }
+    }
+
+    let timing = if matches.get_flag("per-input") {
+        Some(
+            simulation
+                .metadata
+                .timing
+                .as_ref()
+                .ok_or("The simulation file records no input timing")?,
+        )
+    } else {
+        None
+    };
+
+    let mut truth_table = generate_truth_table(
         &design,
         &simulation,
         &cells,
         cell_clock_delay,
-        clock_threshold,
-        cell_threshold,
-        value_threshold,
+        timing,
+        &TruthTableThresholds {
+            clock: clock_threshold,
+            logical: cell_threshold,
+            value: value_threshold,
+        },
     );
+    for bus in &buses {
+        truth_table.decode_bus(bus)?;
+    }
     println!("{}", truth_table);
 
     Ok(())
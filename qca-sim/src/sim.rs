@@ -1,15 +1,42 @@
 use clap::builder::PathBufValueParser;
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, ArgAction, ArgGroup, ArgMatches, Command};
 use indicatif::{ProgressBar, ProgressStyle};
 use qca_core::design::file::{QCADesignFile, DESIGN_FILE_EXTENSION};
 use qca_core::simulation::bistable::BistableModel;
+use qca_core::simulation::clock_generator::{ClockNoise, ClockSignal};
+use qca_core::simulation::coherence_vector::CoherenceVectorModel;
 use qca_core::simulation::file::{write_to_file, SIMULATION_FILE_EXTENSION};
 use qca_core::simulation::icha::ICHAModel;
+use qca_core::simulation::input_generator::{InputTransition, InputWaveform};
+use qca_core::simulation::ising::IsingModel;
 use qca_core::simulation::model::SimulationModelTrait;
-use qca_core::simulation::{get_num_samples, run_simulation_async, SimulationProgress};
+use qca_core::simulation::monte_carlo::MonteCarloModel;
+use qca_core::simulation::qcadesigner::QCADesignerModel;
+use qca_core::simulation::stimulus::Stimulus;
+use qca_core::simulation::{
+    build_generator_configs, estimate_simulation, run_simulation_async, SimulationOptions,
+    SimulationProgress,
+};
 use std::error::Error;
 use std::fs;
 use std::fs::File;
+use std::thread;
+
+const DEFAULT_THREADS: &str = "1";
+
+fn validate_threads(s: &str) -> Result<usize, String> {
+    let value = s
+        .parse::<usize>()
+        .map_err(|_| format!("'{}' is not a valid positive integer", s))?;
+
+    if value == 0 {
+        thread::available_parallelism()
+            .map(|n| n.get())
+            .map_err(|err| err.to_string())
+    } else {
+        Ok(value)
+    }
+}
 
 pub fn get_sim_subcommand() -> Command {
     Command::new("sim")
@@ -32,6 +59,103 @@ pub fn get_sim_subcommand() -> Command {
                 .value_parser(PathBufValueParser::default())
                 .required(false),
         )
+        .arg(
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .help(
+                    "Number of threads calculating the cells, 0 uses all available cores. \
+                     Only used by the jacobi and damped update schemes, ICHA needs \
+                     update_scheme: jacobi",
+                )
+                .default_value(DEFAULT_THREADS)
+                .value_parser(validate_threads)
+                .value_name("THREADS"),
+        )
+        .arg(
+            Arg::new("clock-pwl")
+                .long("clock-pwl")
+                .help("CSV of piecewise-linear clock breakpoints (phase, value per zone) replacing the generated clock")
+                .value_parser(PathBufValueParser::default())
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::new("clock-samples")
+                .long("clock-samples")
+                .help("CSV of clock values per sample (one column per zone) replacing the generated clock")
+                .value_parser(PathBufValueParser::default())
+                .value_name("FILE"),
+        )
+        .group(ArgGroup::new("clock").args(["clock-pwl", "clock-samples"]))
+        .arg(
+            Arg::new("clock-noise")
+                .long("clock-noise")
+                .help("Standard deviation of the clock amplitude noise, relative to the amplitude range")
+                .default_value("0")
+                .value_parser(clap::value_parser!(f64))
+                .value_name("FRACTION"),
+        )
+        .arg(
+            Arg::new("clock-jitter")
+                .long("clock-jitter")
+                .help("Standard deviation of the clock phase jitter, as fraction of the period")
+                .default_value("0")
+                .value_parser(clap::value_parser!(f64))
+                .value_name("FRACTION"),
+        )
+        .arg(
+            Arg::new("clock-skew")
+                .long("clock-skew")
+                .help("Standard deviation of the constant phase skew of every clock signal, as fraction of the period")
+                .default_value("0")
+                .value_parser(clap::value_parser!(f64))
+                .value_name("FRACTION"),
+        )
+        .arg(
+            Arg::new("clock-seed")
+                .long("clock-seed")
+                .help("Seed of the clock noise, jitter and skew")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u64))
+                .value_name("SEED"),
+        )
+        .arg(
+            Arg::new("input-transition")
+                .long("input-transition")
+                .help("Transition of the inputs between input combinations")
+                .default_value("step")
+                .value_parser(["step", "linear", "raised_cosine"])
+                .value_name("TRANSITION"),
+        )
+        .arg(
+            Arg::new("input-ramp-fraction")
+                .long("input-ramp-fraction")
+                .help("Fraction of the input period taken by an input transition")
+                .default_value("0.25")
+                .value_parser(clap::value_parser!(f64))
+                .value_name("FRACTION"),
+        )
+        .arg(
+            Arg::new("input-magnitude")
+                .long("input-magnitude")
+                .help("Polarization magnitude of the driven inputs")
+                .default_value("1")
+                .value_parser(clap::value_parser!(f64))
+                .value_name("MAGNITUDE"),
+        )
+        .arg(
+            Arg::new("stimulus")
+                .long("stimulus")
+                .help("JSON stimulus script with the values per input label and clock cycle, replacing the enumeration of all input combinations")
+                .value_parser(PathBufValueParser::default())
+                .value_name("FILE"),
+        )
+        .arg(
+            Arg::new("estimate")
+                .long("estimate")
+                .help("Print the number of samples and an estimated runtime from simulating the first clock cycle, without running the simulation")
+                .action(ArgAction::SetTrue),
+        )
 }
 
 fn get_simulation_model(model_id: &str) -> Box<dyn SimulationModelTrait> {
@@ -39,6 +163,14 @@ fn get_simulation_model(model_id: &str) -> Box<dyn SimulationModelTrait> {
         Box::new(BistableModel::new()) as Box<dyn SimulationModelTrait>
     } else if model_id == ICHAModel::new().get_unique_id() {
         Box::new(ICHAModel::new()) as Box<dyn SimulationModelTrait>
+    } else if model_id == CoherenceVectorModel::new().get_unique_id() {
+        Box::new(CoherenceVectorModel::new()) as Box<dyn SimulationModelTrait>
+    } else if model_id == IsingModel::new().get_unique_id() {
+        Box::new(IsingModel::new()) as Box<dyn SimulationModelTrait>
+    } else if model_id == MonteCarloModel::new().get_unique_id() {
+        Box::new(MonteCarloModel::new()) as Box<dyn SimulationModelTrait>
+    } else if model_id == QCADesignerModel::new().get_unique_id() {
+        Box::new(QCADesignerModel::new()) as Box<dyn SimulationModelTrait>
     } else {
         panic!("Model {} not found", model_id);
     }
@@ -46,6 +178,7 @@ fn get_simulation_model(model_id: &str) -> Box<dyn SimulationModelTrait> {
 
 pub fn run_sim(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
     let input = matches.get_one::<std::path::PathBuf>("filename").unwrap();
+    let num_threads = *matches.get_one::<usize>("threads").unwrap();
     let output = if let Some(output) = matches.get_one::<std::path::PathBuf>("output") {
         output
     } else {
@@ -73,6 +206,59 @@ pub fn run_sim(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
         .get(&simulation_model_id)
         .unwrap();
 
+    let clock_signal = if let Some(path) = matches.get_one::<std::path::PathBuf>("clock-pwl") {
+        Some(ClockSignal::from_piecewise_linear_csv(
+            &fs::read_to_string(path)?,
+        )?)
+    } else if let Some(path) = matches.get_one::<std::path::PathBuf>("clock-samples") {
+        Some(ClockSignal::from_samples_csv(&fs::read_to_string(path)?)?)
+    } else {
+        None
+    };
+
+    let clock_noise = ClockNoise {
+        seed: *matches.get_one::<u64>("clock-seed").unwrap(),
+        amplitude: *matches.get_one::<f64>("clock-noise").unwrap(),
+        jitter: *matches.get_one::<f64>("clock-jitter").unwrap(),
+        skew: *matches.get_one::<f64>("clock-skew").unwrap(),
+    };
+    let clock_noise =
+        if clock_noise.amplitude != 0.0 || clock_noise.jitter != 0.0 || clock_noise.skew != 0.0 {
+            Some(clock_noise)
+        } else {
+            None
+        };
+
+    let input_waveform = InputWaveform {
+        transition: match matches
+            .get_one::<String>("input-transition")
+            .unwrap()
+            .as_str()
+        {
+            "linear" => InputTransition::Linear,
+            "raised_cosine" => InputTransition::RaisedCosine,
+            _ => InputTransition::Step,
+        },
+        ramp_fraction: *matches.get_one::<f64>("input-ramp-fraction").unwrap(),
+        magnitude: *matches.get_one::<f64>("input-magnitude").unwrap(),
+    };
+
+    let stimulus = match matches.get_one::<std::path::PathBuf>("stimulus") {
+        Some(path) => {
+            let stimulus = Stimulus::from_json(&fs::read_to_string(path)?)?;
+            let unknown_labels = stimulus.unknown_labels(&qca_design.layers);
+            if !unknown_labels.is_empty() {
+                return Err(format!(
+                    "Stimulus inputs not found in the design: {}",
+                    unknown_labels.join(", ")
+                )
+                .into());
+            }
+            Some(stimulus)
+        }
+        None => None,
+    };
+
     let mut sim_model = get_simulation_model(simulation_model_id.as_str());
     sim_model.deserialize_model_settings(&simulation_model_settings.model_settings.to_string())?;
     sim_model.deserialize_clock_generator_settings(
@@ -81,16 +267,47 @@ pub fn run_sim(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
             .to_string(),
     )?;
 
-    let max_samples = get_num_samples(
-        &sim_model,
+    let options = SimulationOptions {
+        num_threads,
+        clock_signal,
+        clock_noise,
+        input_waveform,
+        stimulus,
+    };
+
+    if matches.get_flag("estimate") {
+        let estimate = estimate_simulation(
+            sim_model,
+            qca_design.layers.clone(),
+            qca_design.cell_architectures.clone(),
+            options,
+        );
+        println!("Samples: {}", estimate.num_samples);
+        println!(
+            "Iterations per sample: {:.1} (first {} samples)",
+            estimate.mean_iterations, estimate.calibration_samples
+        );
+        println!(
+            "Estimated runtime: {:.1} s",
+            estimate.duration.as_secs_f64()
+        );
+        return Ok(());
+    }
+
+    let max_samples = build_generator_configs(
+        sim_model.as_ref(),
         &qca_design.layers,
         &qca_design.cell_architectures,
-    ) as u64;
+        &options,
+    )
+    .clock
+    .num_samples as u64;
 
     let (handle, progress_rx, _cancel_tx) = run_simulation_async(
         sim_model,
         qca_design.layers.clone(),
         qca_design.cell_architectures.clone(),
+        options,
     );
 
     let progress_bar = ProgressBar::new(max_samples);
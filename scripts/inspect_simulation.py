@@ -3,7 +3,7 @@ import numpy as np
 import os
 import sys
 
-from load_sim import load_sim_file
+from load_sim import load_convergence, load_sim_file
 
 DESIGN_MEMBER = 'DESIGN.json'
 METADATA_MEMBER = 'METADATA.json'
@@ -27,6 +27,13 @@ print(f'QCA Core design version: {qca_core_design_ver}')
 print(f'QCA Core simulation version: {qca_core_sim_ver}')
 print(f'Num samples: {num_samples}')
 
+convergence = load_convergence(file_arg)
+if convergence:
+    unconverged = [i for i, c in enumerate(convergence) if not c['converged']]
+    print(f'Iterations per sample: max {max(c["iterations"] for c in convergence)}, '
+          f'mean {np.mean([c["iterations"] for c in convergence]):.1f}')
+    print(f'Unconverged samples: {len(unconverged)} {unconverged[:20]}')
+
 report_name = os.path.splitext(os.path.basename(file_arg))[0]
 
 fig, axs = plt.subplots(len(clock_data) + len(sim_cells))
@@ -6,6 +6,7 @@ import tarfile
 DESIGN_MEMBER = 'DESIGN.json'
 METADATA_MEMBER = 'METADATA.json'
 DATA_MEMBER = 'DATA.bin'
+CONVERGENCE_MEMBER = 'CONVERGENCE.json'
 
 
 def load_sim_file(filename: str) -> (object, object, list[list[float]], list[list[list[float]]]):
@@ -33,11 +34,12 @@ def load_sim_file(filename: str) -> (object, object, list[list[float]], list[lis
         sim_model = sim_settings['simulation_model_settings'][sim_settings['selected_simulation_model_id']]
         num_samples = metadata_json['num_samples']
         sim_cells = metadata_json['stored_cells']
+        num_clock_zones = metadata_json.get('num_clock_zones', 4)
 
         sim_data_off = 0
 
         clock_data = []
-        for _ in range(4):
+        for _ in range(num_clock_zones):
             data = [None] * num_samples
             for i in range(num_samples):
                 data[i] = struct.unpack('<d', sim_data[sim_data_off:sim_data_off+8])[0]
@@ -57,3 +59,13 @@ def load_sim_file(filename: str) -> (object, object, list[list[float]], list[lis
             cell_data.append(data)
 
         return (design_json, metadata_json, clock_data, cell_data)
+
+
+def load_convergence(filename: str) -> list[dict]:
+    """Per-sample convergence statistics, empty for files written without them."""
+    with tarfile.open(filename, 'r') as archive:
+        try:
+            convergence = archive.getmember(CONVERGENCE_MEMBER)
+        except KeyError:
+            return []
+        return json.loads(archive.extractfile(convergence).read())